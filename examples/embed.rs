@@ -0,0 +1,115 @@
+//! Mounts the tools of pdflens next to the tools of another rmcp server, which serves one client
+//! over stdio.
+//!
+//! Each session needs its own `PdflensService`, which remembers the root directories and
+//! passwords of its client. Tool calls that the host doesn't handle itself are forwarded to it.
+
+use std::sync::Arc;
+
+use eyre::Result;
+use pdflens_mcp::{AccessPolicy, PdfCache, PdfIndexStore, PdflensService, ToolConfig};
+use rmcp::handler::server::router::tool::ToolRouter;
+use rmcp::handler::server::tool::ToolCallContext;
+use rmcp::model::{
+    CallToolRequestParams, CallToolResult, Implementation, ListToolsResult, PaginatedRequestParams,
+    ServerCapabilities, ServerInfo, SetLevelRequestParams,
+};
+use rmcp::service::{NotificationContext, RequestContext};
+use rmcp::{RoleServer, ServerHandler, ServiceExt};
+
+struct HostServer {
+    tool_router: ToolRouter<Self>,
+    pdflens: PdflensService,
+}
+
+#[rmcp::tool_router]
+impl HostServer {
+    #[rmcp::tool(description = "Tell which server the client is talking to")]
+    fn whoami(&self) -> String {
+        "A host server with the tools of pdflens".to_owned()
+    }
+}
+
+impl ServerHandler for HostServer {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo::new(
+            ServerCapabilities::builder()
+                .enable_logging()
+                .enable_tools()
+                .build(),
+        )
+        .with_server_info(Implementation::new(
+            "pdflens-host",
+            env!("CARGO_PKG_VERSION"),
+        ))
+    }
+
+    // So pdflens can send its logs to the client
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        self.pdflens.on_initialized(context).await;
+    }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), rmcp::ErrorData> {
+        self.pdflens.set_level(request, context).await
+    }
+
+    // So pdflens asks the client for its root directories again
+    async fn on_roots_list_changed(&self, context: NotificationContext<RoleServer>) {
+        self.pdflens.on_roots_list_changed(context).await;
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, rmcp::ErrorData> {
+        let mut tools = self.tool_router.list_all();
+        // Without the tools disabled in `ToolConfig`
+        tools.extend(self.pdflens.tool_router().list_all());
+        Ok(ListToolsResult::with_all_items(tools))
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        if self.tool_router.has_route(&request.name) {
+            self.tool_router
+                .call(ToolCallContext::new(self, request, context))
+                .await
+        } else {
+            self.pdflens.call_tool(request, context).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    // Shared by all sessions
+    let pdf_cache = Arc::new(PdfCache::new(256 * 1024 * 1024));
+    let pdf_index = Arc::new(PdfIndexStore::new(
+        std::env::temp_dir().join("pdflens-host-index"),
+    ));
+    // Only the root directories of the client, read with the default file policy
+    let access_policy = Arc::new(AccessPolicy::new(Default::default()));
+    let tool_config = Arc::new(ToolConfig::default());
+
+    let host = HostServer {
+        tool_router: HostServer::tool_router(),
+        pdflens: PdflensService::new(pdf_cache, pdf_index, access_policy, tool_config),
+    };
+    host.serve(rmcp::transport::stdio())
+        .await?
+        .waiting()
+        .await?;
+    Ok(())
+}
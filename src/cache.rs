@@ -0,0 +1,353 @@
+use std::fs::Metadata;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+use hayro::hayro_syntax::Pdf;
+use indexmap::IndexMap;
+
+/// A parsed PDF, together with what is needed to parse it again in a worker process.
+pub struct LoadedPdf {
+    pub pdf: Pdf,
+    pub data: Arc<Vec<u8>>,
+    /// `None` if the PDF can be opened without a password
+    pub(crate) password: Option<String>,
+}
+
+impl Deref for LoadedPdf {
+    type Target = Pdf;
+
+    fn deref(&self) -> &Pdf {
+        &self.pdf
+    }
+}
+
+/// An LRU cache of parsed PDFs, keyed by canonical path.
+///
+/// Each entry remembers the modification time and size of the file it was parsed from, so a file
+/// that changed on disk is never served from the cache. The memory budget is measured in file
+/// sizes, which is a good enough estimation of what a parsed `Pdf` holds on to.
+///
+/// An encrypted PDF is only served to callers who supply the same password it was opened with.
+pub struct PdfCache {
+    budget: u64,
+    inner: Mutex<PdfCacheInner>,
+}
+
+#[derive(Default)]
+struct PdfCacheInner {
+    // Ordered from the least recently used to the most recently used
+    entries: IndexMap<PathBuf, PdfCacheEntry>,
+    total_size: u64,
+}
+
+struct PdfCacheEntry {
+    modified: Option<SystemTime>,
+    size: u64,
+    password: Option<String>,
+    pdf: Arc<LoadedPdf>,
+}
+
+impl PdfCache {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn get(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        password: Option<&str>,
+    ) -> Option<Arc<LoadedPdf>> {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let idx = inner.entries.get_index_of(path)?;
+        let entry = &inner.entries[idx];
+        if entry.modified != metadata.modified().ok() || entry.size != metadata.len() {
+            let (_, entry) = inner.entries.shift_remove_index(idx)?;
+            inner.total_size -= entry.size;
+            return None;
+        }
+        if entry
+            .password
+            .as_deref()
+            .is_some_and(|expected| Some(expected) != password)
+        {
+            return None;
+        }
+        let pdf = entry.pdf.clone();
+        let last = inner.entries.len() - 1;
+        inner.entries.move_index(idx, last);
+        Some(pdf)
+    }
+
+    /// Like `get`, but does not mark the PDF as recently used, e.g. for a scan over many PDFs.
+    pub fn peek(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        password: Option<&str>,
+    ) -> Option<Arc<LoadedPdf>> {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let entry = inner.entries.get(path)?;
+        if entry.modified != metadata.modified().ok()
+            || entry.size != metadata.len()
+            || entry
+                .password
+                .as_deref()
+                .is_some_and(|expected| Some(expected) != password)
+        {
+            return None;
+        }
+        Some(entry.pdf.clone())
+    }
+
+    /// `password` should be `None` if the PDF can be opened without a password.
+    pub fn insert(
+        &self,
+        path: PathBuf,
+        metadata: &Metadata,
+        password: Option<String>,
+        pdf: Arc<LoadedPdf>,
+    ) {
+        let size = metadata.len();
+        if size > self.budget {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(old) = inner.entries.shift_remove(&path) {
+            inner.total_size -= old.size;
+        }
+        while inner.total_size + size > self.budget {
+            let Some((evicted_path, evicted)) = inner.entries.shift_remove_index(0) else {
+                break;
+            };
+            tracing::debug!("Evicted PDF from cache: {evicted_path:?}");
+            inner.total_size -= evicted.size;
+        }
+        inner.entries.insert(
+            path,
+            PdfCacheEntry {
+                modified: metadata.modified().ok(),
+                size,
+                password,
+                pdf,
+            },
+        );
+        inner.total_size += size;
+    }
+
+    /// Drops a PDF from the cache, e.g. when its file changed on disk.
+    pub fn invalidate(&self, path: &Path) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = inner.entries.shift_remove(path) {
+            tracing::debug!("Invalidated cached PDF: {path:?}");
+            inner.total_size -= entry.size;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::service::PdflensService;
+
+    /// Builds the data of a PDF with a line of text on each page.
+    pub(crate) fn test_pdf_data(page_texts: &[&str]) -> Vec<u8> {
+        // 1 is the catalog, 2 the page tree and 3 the font, followed by each page and its content
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_owned(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..page_texts.len())
+                    .map(|idx| format!("{} 0 R", 4 + 2 * idx))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                page_texts.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_owned(),
+        ];
+        for (idx, text) in page_texts.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                5 + 2 * idx
+            ));
+            let content = format!("BT /F1 24 Tf 72 720 Td ({text}) Tj ET");
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{content}\nendstream",
+                content.len()
+            ));
+        }
+
+        let mut data = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (idx, object) in objects.iter().enumerate() {
+            offsets.push(data.len());
+            data.extend(format!("{} 0 obj\n{object}\nendobj\n", idx + 1).as_bytes());
+        }
+        let xref_offset = data.len();
+        data.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            data.extend(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        data.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .as_bytes(),
+        );
+        data
+    }
+
+    pub(crate) fn test_pdf(page_texts: &[&str]) -> Arc<LoadedPdf> {
+        let data = Arc::new(test_pdf_data(page_texts));
+        let (pdf, _) = PdflensService::parse_pdf(data.clone(), None).unwrap();
+        Arc::new(LoadedPdf {
+            pdf,
+            data,
+            password: None,
+        })
+    }
+
+    /// Creates files of the given sizes in a new directory, and returns their paths.
+    fn create_files(name: &str, sizes: &[usize]) -> (PathBuf, Vec<PathBuf>) {
+        let dir =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = sizes
+            .iter()
+            .enumerate()
+            .map(|(idx, &size)| {
+                let path = dir.join(format!("{idx}.pdf"));
+                std::fs::write(&path, vec![b'x'; size]).unwrap();
+                path
+            })
+            .collect();
+        (dir, paths)
+    }
+
+    fn metadata(path: &Path) -> Metadata {
+        std::fs::metadata(path).unwrap()
+    }
+
+    fn is_cached(cache: &PdfCache, path: &Path, pdf: &Arc<LoadedPdf>) -> bool {
+        cache
+            .peek(path, &metadata(path), None)
+            .is_some_and(|cached| Arc::ptr_eq(&cached, pdf))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let (dir, paths) = create_files("cache-lru", &[100, 100, 100]);
+        let pdfs = [test_pdf(&["0"]), test_pdf(&["1"]), test_pdf(&["2"])];
+        let cache = PdfCache::new(250);
+        cache.insert(
+            paths[0].clone(),
+            &metadata(&paths[0]),
+            None,
+            pdfs[0].clone(),
+        );
+        cache.insert(
+            paths[1].clone(),
+            &metadata(&paths[1]),
+            None,
+            pdfs[1].clone(),
+        );
+        // Now the second file is the least recently used
+        assert!(cache.get(&paths[0], &metadata(&paths[0]), None).is_some());
+        cache.insert(
+            paths[2].clone(),
+            &metadata(&paths[2]),
+            None,
+            pdfs[2].clone(),
+        );
+        assert!(is_cached(&cache, &paths[0], &pdfs[0]));
+        assert!(!is_cached(&cache, &paths[1], &pdfs[1]));
+        assert!(is_cached(&cache, &paths[2], &pdfs[2]));
+
+        // `peek` doesn’t count as a use
+        assert!(cache.peek(&paths[0], &metadata(&paths[0]), None).is_some());
+        cache.insert(
+            paths[1].clone(),
+            &metadata(&paths[1]),
+            None,
+            pdfs[1].clone(),
+        );
+        assert!(!is_cached(&cache, &paths[0], &pdfs[0]));
+        assert!(is_cached(&cache, &paths[1], &pdfs[1]));
+        assert!(is_cached(&cache, &paths[2], &pdfs[2]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_files_over_budget() {
+        let (dir, paths) = create_files("cache-budget", &[100, 1]);
+        let pdf = test_pdf(&["0"]);
+        let cache = PdfCache::new(99);
+        cache.insert(paths[0].clone(), &metadata(&paths[0]), None, pdf.clone());
+        assert!(!is_cached(&cache, &paths[0], &pdf));
+
+        // A budget of 0 disables the cache
+        let cache = PdfCache::new(0);
+        cache.insert(paths[1].clone(), &metadata(&paths[1]), None, pdf.clone());
+        assert!(!is_cached(&cache, &paths[1], &pdf));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalidates_changed_files() {
+        let (dir, paths) = create_files("cache-changed", &[100, 100, 100]);
+        let pdf = test_pdf(&["0"]);
+        let cache = PdfCache::new(1000);
+        for path in &paths {
+            cache.insert(path.clone(), &metadata(path), None, pdf.clone());
+        }
+
+        std::fs::write(&paths[0], vec![b'x'; 101]).unwrap();
+        assert!(cache.get(&paths[0], &metadata(&paths[0]), None).is_none());
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&paths[1])
+            .unwrap();
+        let modified = metadata(&paths[1]).modified().unwrap();
+        file.set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert!(cache.get(&paths[1], &metadata(&paths[1]), None).is_none());
+        cache.invalidate(&paths[2]);
+        assert!(!is_cached(&cache, &paths[2], &pdf));
+
+        // The entries are gone, even once the files look the same again
+        std::fs::write(&paths[0], vec![b'x'; 100]).unwrap();
+        assert!(!is_cached(&cache, &paths[0], &pdf));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn requires_the_same_password() {
+        let (dir, paths) = create_files("cache-password", &[100, 100]);
+        let pdf = test_pdf(&["0"]);
+        let cache = PdfCache::new(1000);
+        let metadata0 = metadata(&paths[0]);
+        cache.insert(
+            paths[0].clone(),
+            &metadata0,
+            Some("secret".to_owned()),
+            pdf.clone(),
+        );
+        assert!(cache.get(&paths[0], &metadata0, None).is_none());
+        assert!(cache.get(&paths[0], &metadata0, Some("wrong")).is_none());
+        assert!(cache.peek(&paths[0], &metadata0, None).is_none());
+        assert!(cache.get(&paths[0], &metadata0, Some("secret")).is_some());
+
+        // A PDF without a password is returned whatever the caller supplies
+        let metadata1 = metadata(&paths[1]);
+        cache.insert(paths[1].clone(), &metadata1, None, pdf.clone());
+        assert!(cache.get(&paths[1], &metadata1, Some("anything")).is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
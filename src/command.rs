@@ -0,0 +1,231 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use eyre::{Result, WrapErr};
+use pdflens_mcp::{PdflensService, WorkerLimits, run_pdf_worker};
+
+/// Runs the same code as the tools on a local PDF, without an MCP client, to reproduce problems.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print the number of pages of a PDF, like `get_pdf_num_pages`
+    Pages {
+        file: PathBuf,
+        /// Only needed if the PDF is encrypted
+        #[arg(long, env = "PDFLENS_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Print the text of a PDF, like `read_pdf_as_text`. Each page is separated by “\x0c” (U+000C).
+    Text {
+        file: PathBuf,
+        /// First page to read
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..).map(|x| x as usize))]
+        from: usize,
+        /// Last page to read [default: the last page]
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..).map(|x| x as usize))]
+        to: Option<usize>,
+        /// Only needed if the PDF is encrypted
+        #[arg(long, env = "PDFLENS_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Render a page of a PDF to PNG, like `read_pdf_page_as_image`
+    Render {
+        file: PathBuf,
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..).map(|x| x as usize))]
+        page: usize,
+        /// Number of pixels on the longer side of the image [default: --image-dimension]
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        dimension: Option<u16>,
+        /// Where to write the PNG file, or “-” for stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+        /// Only needed if the PDF is encrypted
+        #[arg(long, env = "PDFLENS_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Serve text extraction and rendering requests of the server on stdin and stdout
+    #[command(hide = true)]
+    Worker(WorkerLimits),
+}
+
+impl Command {
+    pub async fn run(self, service: PdflensService) -> Result<()> {
+        match self {
+            Self::Pages { file, password } => {
+                let pdf = service.open_pdf(&file, password.as_deref()).await?;
+                println!("{}", pdf.pages().len());
+            }
+            Self::Text {
+                file,
+                from,
+                to,
+                password,
+            } => {
+                let pdf = service.open_pdf(&file, password.as_deref()).await?;
+                let text = service.read_text(&pdf, from, to, None).await?;
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(text.as_bytes())?;
+                if !text.ends_with('\n') {
+                    stdout.write_all(b"\n")?;
+                }
+            }
+            Self::Render {
+                file,
+                page,
+                dimension,
+                output,
+                password,
+            } => {
+                let pdf = service.open_pdf(&file, password.as_deref()).await?;
+                let image = service.render_page(pdf, page, dimension).await?;
+                if output.as_os_str() == "-" {
+                    std::io::stdout().lock().write_all(&image)?;
+                } else {
+                    tokio::fs::write(&output, image)
+                        .await
+                        .wrap_err_with(|| format!("Failed to write {output:?}"))?;
+                }
+            }
+            // Usually started by `main` before the configuration is loaded
+            Self::Worker(limits) => run_pdf_worker(limits)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use indexmap::IndexSet;
+    use pdflens_mcp::{AccessPolicy, PdfCache, PdfIndexStore};
+
+    use super::*;
+
+    /// Writes a PDF with a line of text on each page.
+    fn write_test_pdf(path: &Path, page_texts: &[&str]) {
+        // 1 is the catalog, 2 the page tree and 3 the font, followed by each page and its content
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".to_owned(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..page_texts.len())
+                    .map(|idx| format!("{} 0 R", 4 + 2 * idx))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                page_texts.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_owned(),
+        ];
+        for (idx, text) in page_texts.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                5 + 2 * idx
+            ));
+            let content = format!("BT /F1 24 Tf 72 720 Td ({text}) Tj ET");
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{content}\nendstream",
+                content.len()
+            ));
+        }
+
+        let mut data = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (idx, object) in objects.iter().enumerate() {
+            offsets.push(data.len());
+            data.extend(format!("{} 0 obj\n{object}\nendobj\n", idx + 1).as_bytes());
+        }
+        let xref_offset = data.len();
+        data.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for offset in offsets {
+            data.extend(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        data.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .as_bytes(),
+        );
+        std::fs::write(path, data).unwrap();
+    }
+
+    fn new_service(dir: &Path) -> PdflensService {
+        PdflensService::new(
+            Arc::new(PdfCache::new(0)),
+            Arc::new(PdfIndexStore::new(dir.join("index"))),
+            Arc::new(AccessPolicy::new(IndexSet::new())),
+            Arc::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn subcommands_run_the_tool_code() {
+        let dir =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-command-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("document.pdf");
+        write_test_pdf(&file, &["First page", "Second page"]);
+
+        let service = new_service(&dir);
+        let pdf = service.open_pdf(&file, None).await.unwrap();
+        assert_eq!(pdf.pages().len(), 2);
+        let text = service.read_text(&pdf, 1, None, None).await.unwrap();
+        let pages = text.split('\x0c').collect::<Vec<_>>();
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].contains("First page"));
+        assert!(pages[1].contains("Second page"));
+        let text = service.read_text(&pdf, 2, Some(2), None).await.unwrap();
+        assert!(text.contains("Second page") && !text.contains('\x0c'));
+
+        Command::Pages {
+            file: file.clone(),
+            password: None,
+        }
+        .run(new_service(&dir))
+        .await
+        .unwrap();
+        let output = dir.join("page-2.png");
+        Command::Render {
+            file: file.clone(),
+            page: 2,
+            dimension: Some(64),
+            output: output.clone(),
+            password: None,
+        }
+        .run(new_service(&dir))
+        .await
+        .unwrap();
+        assert!(std::fs::read(&output).unwrap().starts_with(b"\x89PNG"));
+        assert!(
+            Command::Render {
+                file,
+                page: 3,
+                dimension: Some(64),
+                output,
+                password: None,
+            }
+            .run(new_service(&dir))
+            .await
+            .is_err()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn subcommands_fail_on_missing_file() {
+        let file = std::env::temp_dir().join("pdflens-mcp-test-missing.pdf");
+        let dir = std::env::temp_dir();
+        assert!(
+            Command::Pages {
+                file,
+                password: None,
+            }
+            .run(new_service(&dir))
+            .await
+            .is_err()
+        );
+    }
+}
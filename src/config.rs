@@ -0,0 +1,570 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use base64::prelude::*;
+use clap::Parser;
+use eyre::{Result, WrapErr, bail, eyre};
+use globset::{GlobBuilder, GlobSetBuilder};
+use indexmap::IndexSet;
+use pdflens_mcp::{AccessPolicy, ClientRootsMode, ToolConfig, WorkerLimits};
+use serde::Deserialize;
+use url::Url;
+
+use crate::command::Command;
+
+const DEFAULT_CACHE_SIZE_MIB: u64 = 256;
+const DEFAULT_WORKER_MEMORY_MIB: u64 = 4096;
+const DEFAULT_WORKER_CPU_SECS: u64 = 60;
+const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:8080";
+const LOOPBACK_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
+const DEFAULT_HTTP_TOKEN_FILE_NAME: &str = "http-token.txt";
+
+/// An MCP server for reading PDFs, coded by human, designed for AI.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// TOML config file [default: pdflens-mcp/config.toml in the user’s config directory, if it exists]
+    #[arg(long, env = "PDFLENS_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Directory to allow reading PDFs from, in addition to the config file. May be repeated.
+    #[arg(long = "root", value_name = "DIR")]
+    roots: Vec<PathBuf>,
+    /// Glob of paths to never read, e.g. "**/private/**". May be repeated.
+    #[arg(long = "deny", value_name = "GLOB")]
+    deny: Vec<String>,
+    /// Whether the root directories from the MCP client replace or augment the configured roots
+    #[arg(long, value_enum, value_name = "MODE")]
+    client_roots: Option<ClientRootsMode>,
+    /// Allow symbolic links inside a root directory to point outside of it
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    allow_symlink_escape: Option<bool>,
+    /// Memory budget for parsed PDFs in MiB, 0 to disable caching [default: 256]
+    #[arg(long, env = "PDFLENS_CACHE_SIZE_MIB", value_name = "MIB")]
+    cache_size_mib: Option<u64>,
+    /// Directory to store full-text indexes [default: pdflens-mcp in the user’s cache directory]
+    #[arg(long, env = "PDFLENS_INDEX_DIR", value_name = "DIR")]
+    index_dir: Option<PathBuf>,
+    /// Extract text in worker processes, so that a crash on a malformed PDF does not end the server [default: true]
+    #[arg(long, env = "PDFLENS_ISOLATE_TEXT", value_name = "BOOL")]
+    isolate_text: Option<bool>,
+    /// Also render pages in worker processes [default: false]
+    #[arg(long, env = "PDFLENS_ISOLATE_RENDERING", value_name = "BOOL")]
+    isolate_rendering: Option<bool>,
+    /// Address space limit of each worker process in MiB, 0 for unlimited [default: 4096]
+    #[arg(long, value_name = "MIB")]
+    worker_memory_mib: Option<u64>,
+    /// CPU time limit of a worker process for each page in seconds, 0 for unlimited [default: 60]
+    #[arg(long, value_name = "SECS")]
+    worker_cpu_secs: Option<u64>,
+    /// Tool to offer to the MCP client, even if it is disabled by default. May be repeated.
+    #[arg(
+        long = "enable-tool",
+        env = "PDFLENS_ENABLE_TOOLS",
+        value_delimiter = ',',
+        value_name = "TOOL"
+    )]
+    enable_tools: Vec<String>,
+    /// Tool to hide from the MCP client. May be repeated.
+    #[arg(
+        long = "disable-tool",
+        env = "PDFLENS_DISABLE_TOOLS",
+        value_delimiter = ',',
+        value_name = "TOOL"
+    )]
+    disable_tools: Vec<String>,
+    /// Watch the workspace directories for new PDFs as soon as the MCP client lists the resources, instead of after it subscribes to one
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    watch_roots: Option<bool>,
+    /// Number of pixels on the longer side of rendered pages, unless the MCP client asks otherwise [default: 1024]
+    #[arg(
+        long,
+        env = "PDFLENS_IMAGE_DIMENSION",
+        value_name = "PIXELS",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    image_dimension: Option<u16>,
+    /// Serve MCP over streamable HTTP on this address or port, instead of over stdin and stdout [default: 127.0.0.1:8080]
+    #[arg(
+        long,
+        env = "PDFLENS_HTTP",
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_HTTP_ADDR,
+        value_parser = parse_http_addr
+    )]
+    http: Option<SocketAddr>,
+    /// Bearer token that HTTP clients must always send [default: from --http-token-file, or generated once and stored in pdflens-mcp/http-token.txt in the user’s config directory]
+    #[arg(
+        long,
+        env = "PDFLENS_HTTP_TOKEN",
+        hide_env_values = true,
+        value_name = "TOKEN"
+    )]
+    http_token: Option<String>,
+    /// File containing the bearer token, to keep it out of the process list. --http-token takes precedence.
+    #[arg(long, value_name = "FILE")]
+    http_token_file: Option<PathBuf>,
+    /// Host name that the HTTP `Host` and `Origin` headers may contain, besides localhost. May be repeated.
+    #[arg(long = "http-allowed-host", value_name = "HOST")]
+    http_allowed_hosts: Vec<String>,
+    /// Web page origin allowed to connect over HTTP, e.g. "https://example.com". May be repeated.
+    #[arg(long = "http-allowed-origin", value_name = "ORIGIN")]
+    http_allowed_origins: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    #[serde(default)]
+    roots: Vec<PathBuf>,
+    #[serde(default)]
+    deny: Vec<String>,
+    client_roots: Option<ClientRootsMode>,
+    allow_symlink_escape: Option<bool>,
+    cache_size_mib: Option<u64>,
+    index_dir: Option<PathBuf>,
+    isolate_text: Option<bool>,
+    isolate_rendering: Option<bool>,
+    worker_memory_mib: Option<u64>,
+    worker_cpu_secs: Option<u64>,
+    #[serde(default)]
+    enable_tools: Vec<String>,
+    #[serde(default)]
+    disable_tools: Vec<String>,
+    image_dimension: Option<u16>,
+    watch_roots: Option<bool>,
+    http: Option<String>,
+    http_token_file: Option<PathBuf>,
+    #[serde(default)]
+    http_allowed_hosts: Vec<String>,
+    #[serde(default)]
+    http_allowed_origins: Vec<String>,
+}
+
+pub struct Config {
+    pub access_policy: AccessPolicy,
+    pub tool_config: ToolConfig,
+    pub cache_size_mib: u64,
+    pub index_dir: PathBuf,
+    pub isolate_text: bool,
+    pub isolate_rendering: bool,
+    pub worker_limits: WorkerLimits,
+    pub http: Option<HttpConfig>,
+}
+
+/// Decides where the streamable HTTP transport listens, and which requests it accepts.
+pub struct HttpConfig {
+    pub addr: SocketAddr,
+    pub token: String,
+    /// Lowercase host names without ports, e.g. `localhost` or `[::1]`
+    pub allowed_hosts: HashSet<String>,
+    /// Serialized origins of web pages that may connect, e.g. `http://localhost:8080`
+    pub allowed_origins: HashSet<String>,
+}
+
+impl Config {
+    /// Merges the command-line arguments with the config file. Command-line arguments take
+    /// precedence, while lists are concatenated.
+    ///
+    /// `tool_names` lists every tool that the server implements, to validate the tool options.
+    ///
+    /// If `offline`, for the subcommands that read a local PDF, the root directories and the
+    /// HTTP options are not used, so a problem in them does not stop the subcommand.
+    pub fn load(cli: Cli, tool_names: &[String], offline: bool) -> Result<Self> {
+        let config_path = cli.config.clone().or_else(|| {
+            dirs::config_dir()
+                .map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+                .filter(|path| path.is_file())
+        });
+        let file = match &config_path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("Failed to read config file: {path:?}"))?;
+                tracing::info!("Loaded config file: {path:?}");
+                toml::from_str::<ConfigFile>(&text)
+                    .wrap_err_with(|| format!("Failed to parse config file: {path:?}"))?
+            }
+            None => ConfigFile::default(),
+        };
+
+        // Relative paths in the config file are relative to the config file itself
+        let config_dir = config_path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_owned)
+            .unwrap_or_default();
+        let mut roots = IndexSet::new();
+        // The subcommands open the given file directly
+        if !offline {
+            for root in file
+                .roots
+                .iter()
+                .map(|root| config_dir.join(root))
+                .chain(cli.roots)
+            {
+                match std::fs::canonicalize(&root) {
+                    Ok(path) => {
+                        roots.insert(path);
+                    }
+                    Err(err) => tracing::error!("Ignored invalid root path: {root:?} → {err}"),
+                }
+            }
+        }
+
+        let mut access_policy = AccessPolicy::new(roots);
+        access_policy.client_roots = cli.client_roots.or(file.client_roots).unwrap_or_default();
+        access_policy.allow_symlink_escape = cli
+            .allow_symlink_escape
+            .or(file.allow_symlink_escape)
+            .unwrap_or_default();
+        let deny = file.deny.iter().chain(&cli.deny).collect::<Vec<_>>();
+        if !deny.is_empty() {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in deny {
+                builder.add(
+                    GlobBuilder::new(pattern)
+                        .literal_separator(true)
+                        // Otherwise a different case would get around the globs
+                        .case_insensitive(cfg!(any(target_os = "macos", target_os = "windows")))
+                        .build()
+                        .map_err(|err| eyre!("Invalid deny glob: {pattern:?} → {err}"))?,
+                );
+            }
+            access_policy = access_policy.with_deny(builder.build()?);
+        }
+
+        // Command-line arguments are applied last, so they can override the config file
+        let mut tool_config = ToolConfig::default();
+        for (enable_tools, disable_tools) in [
+            (&file.enable_tools, &file.disable_tools),
+            (&cli.enable_tools, &cli.disable_tools),
+        ] {
+            for name in enable_tools.iter().chain(disable_tools) {
+                if !tool_names.contains(name) {
+                    bail!(
+                        "Unknown tool: {name:?}\nAvailable tools: {}",
+                        tool_names.join(", ")
+                    );
+                }
+            }
+            for name in enable_tools {
+                tool_config.disabled_tools.remove(name);
+            }
+            tool_config
+                .disabled_tools
+                .extend(disable_tools.iter().cloned());
+        }
+        if let Some(image_dimension) = cli.image_dimension.or(file.image_dimension) {
+            if image_dimension == 0 {
+                bail!("Invalid image dimension: {image_dimension}");
+            }
+            tool_config.image_dimension = image_dimension;
+        }
+        tool_config.watch_roots = cli.watch_roots.or(file.watch_roots).unwrap_or_default();
+
+        let http_addr = match (cli.http, &file.http) {
+            _ if offline => None,
+            (Some(addr), _) => Some(addr),
+            (None, Some(addr)) => Some(parse_http_addr(addr).map_err(|err| eyre!(err))?),
+            (None, None) => None,
+        };
+        let http = match http_addr {
+            Some(addr) => Some(Self::load_http(
+                addr,
+                cli.http_token,
+                cli.http_token_file
+                    .or(file.http_token_file.map(|path| config_dir.join(path))),
+                file.http_allowed_hosts
+                    .into_iter()
+                    .chain(cli.http_allowed_hosts),
+                file.http_allowed_origins
+                    .into_iter()
+                    .chain(cli.http_allowed_origins),
+            )?),
+            None => None,
+        };
+
+        Ok(Self {
+            access_policy,
+            tool_config,
+            cache_size_mib: cli
+                .cache_size_mib
+                .or(file.cache_size_mib)
+                .unwrap_or(DEFAULT_CACHE_SIZE_MIB),
+            index_dir: cli
+                .index_dir
+                .or(file.index_dir.map(|dir| config_dir.join(dir)))
+                .unwrap_or_else(|| {
+                    dirs::cache_dir()
+                        .unwrap_or_else(std::env::temp_dir)
+                        .join(env!("CARGO_PKG_NAME"))
+                }),
+            isolate_text: cli.isolate_text.or(file.isolate_text).unwrap_or(true),
+            isolate_rendering: cli
+                .isolate_rendering
+                .or(file.isolate_rendering)
+                .unwrap_or_default(),
+            worker_limits: WorkerLimits {
+                max_memory_mib: cli
+                    .worker_memory_mib
+                    .or(file.worker_memory_mib)
+                    .unwrap_or(DEFAULT_WORKER_MEMORY_MIB),
+                max_cpu_secs: cli
+                    .worker_cpu_secs
+                    .or(file.worker_cpu_secs)
+                    .unwrap_or(DEFAULT_WORKER_CPU_SECS),
+            },
+            http,
+        })
+    }
+
+    fn load_http(
+        addr: SocketAddr,
+        token: Option<String>,
+        token_file: Option<PathBuf>,
+        allowed_hosts: impl IntoIterator<Item = String>,
+        allowed_origins: impl IntoIterator<Item = String>,
+    ) -> Result<HttpConfig> {
+        let token = match (token, token_file) {
+            (Some(token), _) => token,
+            (None, Some(path)) => std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("Failed to read HTTP token file: {path:?}"))?
+                .trim()
+                .to_owned(),
+            // Even a loopback address is reachable by every program on this machine
+            (None, None) => Self::load_default_http_token()?,
+        };
+        if token.is_empty() {
+            bail!("The HTTP token is empty");
+        }
+
+        let mut hosts = LOOPBACK_HOSTS
+            .iter()
+            .map(|&host| host.to_owned())
+            .collect::<HashSet<_>>();
+        if !addr.ip().is_unspecified() {
+            hosts.insert(match addr.ip() {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("[{ip}]"),
+            });
+        }
+        hosts.extend(
+            allowed_hosts
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase()),
+        );
+
+        // Web pages served on the same host and port, e.g. behind a reverse proxy
+        let mut origins = hosts
+            .iter()
+            .filter_map(|host| serialize_origin(&format!("http://{host}:{}", addr.port())))
+            .collect::<HashSet<_>>();
+        for origin in allowed_origins {
+            origins.insert(
+                serialize_origin(&origin)
+                    .ok_or_else(|| eyre!("Invalid HTTP allowed origin: {origin:?}"))?,
+            );
+        }
+
+        Ok(HttpConfig {
+            addr,
+            token,
+            allowed_hosts: hosts,
+            allowed_origins: origins,
+        })
+    }
+
+    /// Reads the token that clients must send when none is configured, generating a random one
+    /// on the first start. The file is only readable by the current user.
+    fn load_default_http_token() -> Result<String> {
+        let dir = dirs::config_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(env!("CARGO_PKG_NAME"));
+        let path = dir.join(DEFAULT_HTTP_TOKEN_FILE_NAME);
+        match std::fs::read_to_string(&path) {
+            Ok(token) if !token.trim().is_empty() => {
+                tracing::info!("HTTP clients must send the bearer token in {path:?}");
+                return Ok(token.trim().to_owned());
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("Failed to read HTTP token file: {path:?}"));
+            }
+        }
+
+        let mut bytes = [0; 32];
+        getrandom::fill(&mut bytes).map_err(|err| eyre!("Failed to generate HTTP token: {err}"))?;
+        let token = BASE64_URL_SAFE_NO_PAD.encode(bytes);
+        std::fs::create_dir_all(&dir)
+            .wrap_err_with(|| format!("Failed to create directory: {dir:?}"))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&path)
+            .and_then(|mut file| file.write_all(token.as_bytes()))
+            .wrap_err_with(|| format!("Failed to write HTTP token file: {path:?}"))?;
+        tracing::warn!("Generated an HTTP bearer token, which clients must send: {path:?}");
+        Ok(token)
+    }
+}
+
+/// Serializes an origin like browsers do in the `Origin` header, e.g. `http://localhost:8080`,
+/// without the default port. Returns `None` if it is not a URL.
+pub fn serialize_origin(origin: &str) -> Option<String> {
+    let origin = Url::parse(origin).ok()?.origin();
+    origin.is_tuple().then(|| origin.ascii_serialization())
+}
+
+/// Parses `ADDR:PORT`, or only `PORT` to listen on the loopback address.
+fn parse_http_addr(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = value.parse::<u16>() {
+        return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    }
+    value
+        .parse()
+        .map_err(|err| format!("Invalid HTTP address: {value:?} → {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_load_ignores_roots_and_http() {
+        let dir =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.toml");
+        std::fs::write(&config_path, "").unwrap();
+        let args = [
+            "pdflens-mcp",
+            "--config",
+            config_path.to_str().unwrap(),
+            "--root",
+            dir.join("missing-root").to_str().unwrap(),
+            "--http",
+            "0.0.0.0:8080",
+            "--http-token-file",
+            dir.join("missing-token.txt").to_str().unwrap(),
+        ];
+
+        let cli = Cli::try_parse_from(args.iter().chain(&["pages", "document.pdf"])).unwrap();
+        let config = Config::load(cli, &[], true).unwrap();
+        assert!(config.http.is_none());
+        assert!(config.access_policy.roots.is_empty());
+
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert!(Config::load(cli, &[], false).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn offline_load_still_checks_tool_names() {
+        let cli = Cli::try_parse_from(["pdflens-mcp", "--disable-tool", "no_such_tool"]).unwrap();
+        assert!(Config::load(cli, &["read_pdf_as_text".to_owned()], true).is_err());
+    }
+
+    #[test]
+    fn http_allows_own_hosts_and_origins() {
+        let http = Config::load_http(
+            "0.0.0.0:8080".parse().unwrap(),
+            Some("token".to_owned()),
+            None,
+            ["Example.COM".to_owned()],
+            ["https://App.example.com:443/path".to_owned()],
+        )
+        .unwrap();
+        assert_eq!(http.token, "token");
+        for host in ["localhost", "127.0.0.1", "[::1]", "example.com"] {
+            assert!(http.allowed_hosts.contains(host), "{host}");
+        }
+        assert!(!http.allowed_hosts.contains("0.0.0.0"));
+        for origin in [
+            "http://localhost:8080",
+            "http://[::1]:8080",
+            "http://example.com:8080",
+            "https://app.example.com",
+        ] {
+            assert!(http.allowed_origins.contains(origin), "{origin}");
+        }
+        assert!(!http.allowed_origins.contains("https://localhost:8080"));
+
+        let http = Config::load_http(
+            "192.168.1.2:80".parse().unwrap(),
+            Some("token".to_owned()),
+            None,
+            [],
+            [],
+        )
+        .unwrap();
+        assert!(http.allowed_hosts.contains("192.168.1.2"));
+        assert!(http.allowed_origins.contains("http://192.168.1.2"));
+    }
+
+    #[test]
+    fn http_rejects_bad_tokens_and_origins() {
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        assert!(Config::load_http(addr, Some(String::new()), None, [], []).is_err());
+        assert!(
+            Config::load_http(
+                addr,
+                Some("token".to_owned()),
+                None,
+                [],
+                ["not an origin".to_owned()]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn http_token_file_is_trimmed() {
+        let path = std::env::temp_dir().join(format!(
+            "pdflens-mcp-test-http-token-{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, " token\n").unwrap();
+        let http = Config::load_http(
+            "127.0.0.1:8080".parse().unwrap(),
+            None,
+            Some(path.clone()),
+            [],
+            [],
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(http.unwrap().token, "token");
+    }
+
+    #[test]
+    fn origins_are_serialized_like_browsers() {
+        assert_eq!(
+            serialize_origin("HTTP://LocalHost:80/").as_deref(),
+            Some("http://localhost")
+        );
+        assert_eq!(
+            serialize_origin("http://[::1]:8080").as_deref(),
+            Some("http://[::1]:8080")
+        );
+        assert_eq!(serialize_origin("null"), None);
+        assert_eq!(serialize_origin("file:///index.html"), None);
+    }
+
+    #[test]
+    fn http_addr_defaults_to_loopback() {
+        assert_eq!(
+            parse_http_addr("8080"),
+            Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, 8080)))
+        );
+        assert_eq!(parse_http_addr("[::]:80"), Ok("[::]:80".parse().unwrap()));
+        assert!(parse_http_addr("localhost:8080").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use eyre::{Result, WrapErr};
+use pdflens_mcp::PdflensService;
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use rmcp::transport::streamable_http_server::{StreamableHttpServerConfig, StreamableHttpService};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{HttpConfig, serialize_origin};
+
+const MCP_PATH: &str = "/mcp";
+
+/// Serves MCP over streamable HTTP until the process receives Ctrl-C or SIGTERM.
+///
+/// Each MCP session gets its own `PdflensService` from `new_service`, so root directories,
+/// passwords and subscriptions are never shared between clients.
+pub async fn serve(
+    http_config: HttpConfig,
+    new_service: impl Fn() -> PdflensService + Send + Sync + 'static,
+) -> Result<()> {
+    let ct = CancellationToken::new();
+    let mut config = StreamableHttpServerConfig::default();
+    // Closes the open SSE streams, otherwise the graceful shutdown waits for them forever
+    config.cancellation_token = ct.child_token();
+    let service = StreamableHttpService::new(
+        move || Ok(new_service()),
+        LocalSessionManager::default().into(),
+        config,
+    );
+    let addr = http_config.addr;
+    let router = axum::Router::new().nest_service(MCP_PATH, service).layer(
+        axum::middleware::from_fn_with_state(Arc::new(http_config), check_request),
+    );
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .wrap_err_with(|| format!("Failed to listen on {addr}"))?;
+    tracing::info!("Serving MCP on http://{}{MCP_PATH}", listener.local_addr()?);
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            tracing::info!("Shutting down");
+            ct.cancel();
+        })
+        .await?;
+    Ok(())
+}
+
+/// Rejects requests without the bearer token, and requests from web pages or DNS rebinding
+/// attacks, before they reach the MCP session.
+async fn check_request(
+    State(http_config): State<Arc<HttpConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Err(response) = check_headers(&http_config, request.headers()) {
+        return response;
+    }
+    next.run(request).await
+}
+
+fn check_headers(http_config: &HttpConfig, headers: &HeaderMap) -> Result<(), Response> {
+    // A DNS rebinding attack sends the attacker’s host name
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+    if !http_config.allowed_hosts.contains(&strip_port(host)) {
+        tracing::warn!("Rejected HTTP request with disallowed host: {host:?}");
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!(
+                "Host not allowed: {host:?}\nStart pdflens with `--http-allowed-host` to allow it."
+            ),
+        )
+            .into_response());
+    }
+
+    // Browsers send the origin of the web page, while other MCP clients usually send none.
+    // The scheme and port matter too, as any other web app on localhost has the same host.
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        let origin = origin.to_str().unwrap_or_default();
+        let allowed = serialize_origin(origin)
+            .is_some_and(|origin| http_config.allowed_origins.contains(&origin));
+        if !allowed {
+            tracing::warn!("Rejected HTTP request with disallowed origin: {origin:?}");
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!(
+                    "Origin not allowed: {origin:?}\nStart pdflens with `--http-allowed-origin` to allow it."
+                ),
+            )
+                .into_response());
+        }
+    }
+
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| {
+            constant_time_eq(value.trim().as_bytes(), http_config.token.as_bytes())
+        });
+    if !authorized {
+        tracing::warn!("Rejected HTTP request without a valid bearer token");
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Missing or invalid bearer token in the `Authorization` header",
+        )
+            .into_response());
+    }
+    Ok(())
+}
+
+/// Removes the port from a `Host` header, e.g. `localhost:8080` or `[::1]:8080`.
+fn strip_port(host: &str) -> String {
+    let host = if host.starts_with('[') {
+        host.find(']').map_or(host, |end| &host[..=end])
+    } else {
+        host.rsplit_once(':').map_or(host, |(host, _)| host)
+    };
+    host.to_ascii_lowercase()
+}
+
+// Does not leak the length of the matching prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    let mut terminate = std::pin::pin!(terminate);
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(err) = result {
+                tracing::error!("Failed to listen for Ctrl-C: {err}");
+                // Keep serving until SIGTERM instead
+                terminate.await;
+            }
+        }
+        () = &mut terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use axum::http::HeaderName;
+
+    use super::*;
+
+    fn http_config() -> HttpConfig {
+        HttpConfig {
+            addr: "127.0.0.1:8080".parse().unwrap(),
+            token: "token".to_owned(),
+            allowed_hosts: ["localhost", "127.0.0.1", "[::1]"]
+                .map(str::to_owned)
+                .into(),
+            allowed_origins: HashSet::from(["http://localhost:8080".to_owned()]),
+        }
+    }
+
+    /// Returns the status code of a rejected request.
+    fn check(headers: &[(HeaderName, &str)]) -> Option<StatusCode> {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect::<HeaderMap>();
+        check_headers(&http_config(), &headers)
+            .err()
+            .map(|response| response.status())
+    }
+
+    #[test]
+    fn accepts_allowed_host_and_token() {
+        assert_eq!(
+            check(&[
+                (header::HOST, "localhost:8080"),
+                (header::AUTHORIZATION, "Bearer token"),
+            ]),
+            None
+        );
+        assert_eq!(
+            check(&[
+                (header::HOST, "[::1]:8080"),
+                (header::ORIGIN, "http://LOCALHOST:8080"),
+                (header::AUTHORIZATION, "Bearer token"),
+            ]),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_other_hosts() {
+        for host in ["attacker.example:8080", "localhost.attacker.example", ""] {
+            assert_eq!(
+                check(&[
+                    (header::HOST, host),
+                    (header::AUTHORIZATION, "Bearer token")
+                ]),
+                Some(StatusCode::FORBIDDEN),
+                "{host}"
+            );
+        }
+        assert_eq!(
+            check(&[(header::AUTHORIZATION, "Bearer token")]),
+            Some(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn rejects_other_origins() {
+        for origin in [
+            "http://localhost:3000",
+            "https://localhost:8080",
+            "http://attacker.example",
+            "null",
+        ] {
+            assert_eq!(
+                check(&[
+                    (header::HOST, "localhost:8080"),
+                    (header::ORIGIN, origin),
+                    (header::AUTHORIZATION, "Bearer token"),
+                ]),
+                Some(StatusCode::FORBIDDEN),
+                "{origin}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_token() {
+        assert_eq!(
+            check(&[(header::HOST, "localhost:8080")]),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        for authorization in ["Bearer wrong", "Bearer tokentoken", "Basic token", "token"] {
+            assert_eq!(
+                check(&[
+                    (header::HOST, "localhost:8080"),
+                    (header::AUTHORIZATION, authorization),
+                ]),
+                Some(StatusCode::UNAUTHORIZED),
+                "{authorization}"
+            );
+        }
+    }
+
+    #[test]
+    fn strips_ports_from_hosts() {
+        assert_eq!(strip_port("localhost:8080"), "localhost");
+        assert_eq!(strip_port("LocalHost"), "localhost");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port("127.0.0.1:80"), "127.0.0.1");
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
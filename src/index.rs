@@ -0,0 +1,797 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+use eyre::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// Bump when the on-disk format or the tokenizer changes, so old indexes are rebuilt
+const INDEX_VERSION: u32 = 4;
+const MAX_TERM_LEN: usize = 64;
+// Merge the segments once there are more, so dead postings don’t pile up
+const MAX_SEGMENTS: usize = 8;
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+const LOCK_FILE_NAME: &str = "index.lock";
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// On-disk full-text indexes of the PDFs in each root directory, loaded on first use.
+pub struct PdfIndexStore {
+    dir: PathBuf,
+    indexes: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<PdfIndex>>>>,
+}
+
+/// An inverted index where each page of each PDF is a document for BM25 ranking.
+///
+/// It is stored in a directory with a manifest and several segments. The manifest lists the
+/// indexed files with the lengths of their pages, and each segment holds the postings of the
+/// files indexed in one batch. Saving only writes the manifest and one new segment. A file that
+/// is indexed again gets a new ID, so its old postings are ignored until the segments are merged.
+///
+/// Several processes may share the directory. They only write to it while holding a lock file,
+/// and if another process saved the index in the meantime, its version is loaded again before
+/// the changes of this process are applied on top.
+pub struct PdfIndex {
+    dir: PathBuf,
+    manifest: IndexManifest,
+    segments: BTreeMap<u64, Segment>,
+    // File ID → relative path, for the files in the manifest
+    file_ids: HashMap<u64, PathBuf>,
+    // The segment that receives new files until the next save
+    pending_segment: Option<u64>,
+    // Changes since the last save, to apply again if another process saved the index in between
+    changes: Vec<IndexChange>,
+}
+
+enum IndexChange {
+    Insert(PathBuf, IndexedFile),
+    Remove(PathBuf),
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexManifest {
+    version: u32,
+    root: PathBuf,
+    // Incremented on every save, to tell whether another process saved the index
+    generation: u64,
+    next_file_id: u64,
+    next_segment_id: u64,
+    // Keyed by paths relative to the root directory
+    files: BTreeMap<PathBuf, IndexedFileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedFileEntry {
+    id: u64,
+    modified: Option<SystemTime>,
+    size: u64,
+    // Encrypted PDFs are recorded without their text, so it never ends up on disk
+    encrypted: bool,
+    // `None` if the file has no words
+    segment: Option<u64>,
+    page_lens: Vec<u32>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Segment {
+    // Term → (file ID, page index, term frequency)
+    terms: HashMap<String, Vec<(u64, u32, u32)>>,
+}
+
+/// The words of a PDF, ready to be added to an index.
+pub struct IndexedFile {
+    modified: Option<SystemTime>,
+    size: u64,
+    encrypted: bool,
+    pages: Vec<IndexedPage>,
+}
+
+struct IndexedPage {
+    len: u32,
+    terms: Vec<(String, u32)>,
+}
+
+pub struct IndexStats {
+    pub num_pages: usize,
+    pub total_len: u64,
+}
+
+pub struct IndexHit {
+    pub relative_path: PathBuf,
+    pub page_idx: usize,
+    pub score: f64,
+    pub matched_terms: Vec<String>,
+}
+
+impl PdfIndexStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            indexes: Mutex::default(),
+        }
+    }
+
+    pub async fn open(&self, root: &Path) -> Arc<tokio::sync::Mutex<PdfIndex>> {
+        let index = self
+            .indexes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(root)
+            .cloned();
+        if let Some(index) = index {
+            return index;
+        }
+
+        let name = format!("{:016x}", fnv1a(root));
+        // Before version 3, each index was a single JSON file
+        let _ = tokio::fs::remove_file(self.dir.join(format!("{name}.json"))).await;
+        let index = Arc::new(tokio::sync::Mutex::new(
+            PdfIndex::load(self.dir.join(name), root).await,
+        ));
+        self.indexes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(root.to_owned())
+            .or_insert(index)
+            .clone()
+    }
+}
+
+impl PdfIndex {
+    /// Returns an empty index if the directory contains none, or an outdated or broken one.
+    async fn load(dir: PathBuf, root: &Path) -> Self {
+        // Without the directory, there is no index to read
+        let _lock = lock_dir(&dir, false).await.ok();
+        Self::read(dir, root).await
+    }
+
+    /// Like `load`, but the caller must hold the lock file.
+    async fn read(dir: PathBuf, root: &Path) -> Self {
+        let mut index = Self::empty(dir, root);
+        let manifest_path = index.dir.join(MANIFEST_FILE_NAME);
+        let manifest = match read_json::<IndexManifest>(&manifest_path).await {
+            Ok(Some(manifest)) if manifest.version == INDEX_VERSION && manifest.root == root => {
+                manifest
+            }
+            Ok(Some(_)) => {
+                tracing::info!("Rebuilding outdated full-text index: {:?}", index.dir);
+                return index;
+            }
+            Ok(None) => return index,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to read full-text index, rebuilding: {manifest_path:?} → {err}"
+                );
+                return index;
+            }
+        };
+
+        let segment_ids = manifest
+            .files
+            .values()
+            .filter_map(|file| file.segment)
+            .collect::<HashSet<_>>();
+        let mut segments = BTreeMap::new();
+        for segment_id in segment_ids {
+            let segment_path = index.dir.join(segment_file_name(segment_id));
+            match read_json::<Segment>(&segment_path).await {
+                Ok(Some(segment)) => {
+                    segments.insert(segment_id, segment);
+                }
+                Ok(None) => {
+                    tracing::error!(
+                        "Missing segment of full-text index, rebuilding: {segment_path:?}"
+                    );
+                    return index;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        "Failed to read full-text index, rebuilding: {segment_path:?} → {err}"
+                    );
+                    return index;
+                }
+            }
+        }
+
+        index.file_ids = manifest
+            .files
+            .iter()
+            .map(|(relative_path, file)| (file.id, relative_path.clone()))
+            .collect();
+        index.manifest = manifest;
+        index.segments = segments;
+        index
+    }
+
+    fn empty(dir: PathBuf, root: &Path) -> Self {
+        Self {
+            dir,
+            manifest: IndexManifest {
+                version: INDEX_VERSION,
+                root: root.to_owned(),
+                generation: 0,
+                next_file_id: 0,
+                next_segment_id: 0,
+                files: BTreeMap::new(),
+            },
+            segments: BTreeMap::new(),
+            file_ids: HashMap::new(),
+            pending_segment: None,
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn is_fresh(&self, relative_path: &Path, metadata: &Metadata) -> bool {
+        self.manifest.files.get(relative_path).is_some_and(|file| {
+            file.modified == metadata.modified().ok() && file.size == metadata.len()
+        })
+    }
+
+    pub fn insert(&mut self, relative_path: PathBuf, file: IndexedFile) {
+        self.apply_insert(relative_path.clone(), &file);
+        self.changes.push(IndexChange::Insert(relative_path, file));
+    }
+
+    fn apply_insert(&mut self, relative_path: PathBuf, file: &IndexedFile) {
+        if let Some(old) = self.manifest.files.get(&relative_path) {
+            self.file_ids.remove(&old.id);
+        }
+        let id = self.manifest.next_file_id;
+        self.manifest.next_file_id += 1;
+
+        let segment_id = if file.pages.iter().any(|page| !page.terms.is_empty()) {
+            let segment_id = *self.pending_segment.get_or_insert_with(|| {
+                let segment_id = self.manifest.next_segment_id;
+                self.manifest.next_segment_id += 1;
+                segment_id
+            });
+            let segment = self.segments.entry(segment_id).or_default();
+            for (page_idx, page) in file.pages.iter().enumerate() {
+                for (term, term_freq) in &page.terms {
+                    segment.terms.entry(term.clone()).or_default().push((
+                        id,
+                        page_idx as u32,
+                        *term_freq,
+                    ));
+                }
+            }
+            Some(segment_id)
+        } else {
+            None
+        };
+
+        self.file_ids.insert(id, relative_path.clone());
+        self.manifest.files.insert(
+            relative_path,
+            IndexedFileEntry {
+                id,
+                modified: file.modified,
+                size: file.size,
+                encrypted: file.encrypted,
+                segment: segment_id,
+                page_lens: file.pages.iter().map(|page| page.len).collect(),
+            },
+        );
+    }
+
+    /// Forgets the files that no longer exist.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> bool) {
+        let removed = self
+            .manifest
+            .files
+            .keys()
+            .filter(|relative_path| !keep(relative_path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for relative_path in removed {
+            self.remove(relative_path);
+        }
+    }
+
+    fn remove(&mut self, relative_path: PathBuf) {
+        if let Some(file) = self.manifest.files.remove(&relative_path) {
+            self.file_ids.remove(&file.id);
+            self.changes.push(IndexChange::Remove(relative_path));
+        }
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.manifest.files.len()
+    }
+
+    /// Relative paths of the PDFs that were skipped because they are encrypted.
+    pub fn encrypted_files(&self) -> impl Iterator<Item = &Path> {
+        self.manifest
+            .files
+            .iter()
+            .filter(|(_, file)| file.encrypted)
+            .map(|(relative_path, _)| relative_path.as_path())
+    }
+
+    /// Writes the new segment and the manifest, then deletes the segments that are not needed
+    /// anymore.
+    pub async fn save(&mut self) -> Result<()> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let _lock = lock_dir(&self.dir, true).await?;
+
+        // Segment IDs are only picked, and segments only deleted, from the latest manifest
+        let manifest_path = self.dir.join(MANIFEST_FILE_NAME);
+        let saved_generation = match read_json::<IndexManifest>(&manifest_path).await {
+            Ok(Some(manifest))
+                if manifest.version == INDEX_VERSION && manifest.root == self.manifest.root =>
+            {
+                manifest.generation
+            }
+            _ => 0,
+        };
+        if saved_generation != self.manifest.generation {
+            tracing::info!(
+                "Full-text index was saved by another process, reloading: {:?}",
+                self.dir
+            );
+            let changes = std::mem::take(&mut self.changes);
+            let root = self.manifest.root.clone();
+            *self = Self::read(self.dir.clone(), &root).await;
+            for change in changes {
+                match change {
+                    IndexChange::Insert(relative_path, file) => self.insert(relative_path, file),
+                    IndexChange::Remove(relative_path) => self.remove(relative_path),
+                }
+            }
+        }
+        self.manifest.generation += 1;
+
+        let live_segments = self
+            .manifest
+            .files
+            .values()
+            .filter_map(|file| file.segment)
+            .collect::<HashSet<_>>();
+        self.segments
+            .retain(|segment_id, _| live_segments.contains(segment_id));
+        if self.segments.len() > MAX_SEGMENTS {
+            self.merge_segments();
+        }
+
+        if let Some(segment_id) = self.pending_segment
+            && let Some(segment) = self.segments.get(&segment_id)
+        {
+            write_json(&self.dir.join(segment_file_name(segment_id)), segment).await?;
+        }
+        // The manifest is written last, so it never refers to a missing segment
+        write_json(&manifest_path, &self.manifest).await?;
+        self.pending_segment = None;
+        self.changes.clear();
+        tracing::info!("Saved full-text index: {:?}", self.dir);
+
+        // Other processes only write segments while holding the lock, so every segment that the
+        // manifest doesn't refer to is unused
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(segment_id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("segment-"))
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|segment_id| segment_id.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if !self.segments.contains_key(&segment_id)
+                && let Err(err) = tokio::fs::remove_file(entry.path()).await
+            {
+                tracing::warn!(
+                    "Failed to delete old segment of full-text index: {:?} → {err}",
+                    entry.path()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges every segment into a new one, dropping the postings of files that were removed or
+    /// indexed again.
+    fn merge_segments(&mut self) {
+        let segment_id = self.manifest.next_segment_id;
+        self.manifest.next_segment_id += 1;
+        let mut merged = Segment::default();
+        for segment in std::mem::take(&mut self.segments).into_values() {
+            for (term, postings) in segment.terms {
+                let live_postings = postings
+                    .into_iter()
+                    .filter(|(file_id, _, _)| self.file_ids.contains_key(file_id))
+                    .collect::<Vec<_>>();
+                if !live_postings.is_empty() {
+                    merged.terms.entry(term).or_default().extend(live_postings);
+                }
+            }
+        }
+        for file in self.manifest.files.values_mut() {
+            if file.segment.is_some() {
+                file.segment = Some(segment_id);
+            }
+        }
+        self.segments.insert(segment_id, merged);
+        self.pending_segment = Some(segment_id);
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        let mut stats = IndexStats {
+            num_pages: 0,
+            total_len: 0,
+        };
+        for &len in self
+            .manifest
+            .files
+            .values()
+            .flat_map(|file| &file.page_lens)
+        {
+            stats.num_pages += 1;
+            stats.total_len += u64::from(len);
+        }
+        stats
+    }
+
+    pub fn doc_freq(&self, term: &str) -> usize {
+        self.postings(term).count()
+    }
+
+    /// Ranks pages with BM25. `stats` and `doc_freqs` should cover every index being searched,
+    /// so that scores from different root directories are comparable.
+    pub fn search(
+        &self,
+        terms: &[String],
+        stats: &IndexStats,
+        doc_freqs: &HashMap<String, usize>,
+    ) -> Vec<IndexHit> {
+        let num_pages = stats.num_pages as f64;
+        let avg_len = stats.total_len as f64 / num_pages.max(1.0);
+
+        let mut scores = HashMap::<(u64, usize), (f64, Vec<String>)>::new();
+        for term in terms {
+            let doc_freq = doc_freqs.get(term).copied().unwrap_or_default();
+            let idf = idf(num_pages, doc_freq as f64);
+            for (file_id, file, page_idx, term_freq) in self.postings(term) {
+                let Some(&len) = file.page_lens.get(page_idx) else {
+                    continue;
+                };
+                let entry = scores.entry((file_id, page_idx)).or_default();
+                entry.0 += bm25(idf, term_freq, len, avg_len);
+                entry.1.push(term.clone());
+            }
+        }
+
+        scores
+            .into_iter()
+            .map(|((file_id, page_idx), (score, matched_terms))| IndexHit {
+                relative_path: self.file_ids[&file_id].clone(),
+                page_idx,
+                score,
+                matched_terms,
+            })
+            .collect()
+    }
+
+    /// Returns the postings of a term in the files that are still indexed, as (file ID, file,
+    /// page index, term frequency).
+    fn postings<'a>(
+        &'a self,
+        term: &'a str,
+    ) -> impl Iterator<Item = (u64, &'a IndexedFileEntry, usize, u32)> + 'a {
+        self.segments
+            .values()
+            .filter_map(move |segment| segment.terms.get(term))
+            .flatten()
+            .filter_map(|&(file_id, page_idx, term_freq)| {
+                let relative_path = self.file_ids.get(&file_id)?;
+                let file = self.manifest.files.get(relative_path)?;
+                Some((file_id, file, page_idx as usize, term_freq))
+            })
+    }
+}
+
+impl IndexedFile {
+    /// Builds the index entry of a PDF from the text of its pages.
+    pub fn new<'a>(metadata: &Metadata, page_texts: impl IntoIterator<Item = &'a str>) -> Self {
+        let pages = page_texts
+            .into_iter()
+            .map(|text| {
+                let mut terms = HashMap::<String, u32>::new();
+                let mut len = 0;
+                for term in tokenize(text) {
+                    *terms.entry(term).or_default() += 1;
+                    len += 1;
+                }
+                IndexedPage {
+                    len,
+                    terms: terms.into_iter().collect(),
+                }
+            })
+            .collect();
+        Self {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            encrypted: false,
+            pages,
+        }
+    }
+
+    /// Records an encrypted PDF without its text, so it isn’t tried again until it changes on
+    /// disk.
+    pub fn encrypted(metadata: &Metadata) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            encrypted: true,
+            pages: Vec::new(),
+        }
+    }
+}
+
+fn idf(num_pages: f64, doc_freq: f64) -> f64 {
+    (1.0 + (num_pages - doc_freq + 0.5) / (doc_freq + 0.5)).ln()
+}
+
+fn bm25(idf: f64, term_freq: u32, len: u32, avg_len: f64) -> f64 {
+    let term_freq = f64::from(term_freq);
+    idf * term_freq * (BM25_K1 + 1.0)
+        / (term_freq + BM25_K1 * (1.0 - BM25_B + BM25_B * f64::from(len) / avg_len))
+}
+
+/// Splits text into lowercase alphanumeric words. CJK ideographs, which aren’t separated by
+/// spaces, become one term per character.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .flat_map(|word| {
+            word.split_inclusive(is_cjk)
+                .flat_map(|part| match part.char_indices().last() {
+                    Some((idx, c)) if is_cjk(c) && idx != 0 => {
+                        [Some(&part[..idx]), Some(&part[idx..])]
+                    }
+                    _ => [Some(part), None],
+                })
+                .flatten()
+        })
+        .filter(|term| !term.is_empty() && term.len() <= MAX_TERM_LEN)
+        .map(str::to_lowercase)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2fa1f}'
+    )
+}
+
+/// Locks the index directory against other processes until the returned file is closed.
+async fn lock_dir(dir: &Path, exclusive: bool) -> Result<std::fs::File> {
+    let path = dir.join(LOCK_FILE_NAME);
+    let file = tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok::<_, std::io::Error>(file)
+    })
+    .await??;
+    Ok(file)
+}
+
+fn segment_file_name(segment_id: u64) -> String {
+    format!("segment-{segment_id}.json")
+}
+
+/// Returns `None` if the file does not exist.
+async fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes to a temporary file first, so a crash never leaves a truncated file.
+async fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let bytes = serde_json::to_vec(value)?;
+    let temp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&temp_path, bytes).await?;
+    tokio::fs::rename(&temp_path, path).await?;
+    Ok(())
+}
+
+/// A stable hash for naming index files, unlike `DefaultHasher`, which may change between Rust
+/// releases.
+fn fnv1a(path: &Path) -> u64 {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_metadata() -> Metadata {
+        std::fs::metadata(std::env::temp_dir()).unwrap()
+    }
+
+    fn insert(index: &mut PdfIndex, relative_path: &str, page_texts: &[&str]) {
+        let file = IndexedFile::new(&test_metadata(), page_texts.iter().copied());
+        index.insert(PathBuf::from(relative_path), file);
+    }
+
+    /// Returns the hits as (relative path, page index), from the best to the worst.
+    fn search(index: &PdfIndex, terms: &[&str]) -> Vec<(String, usize)> {
+        let terms = terms
+            .iter()
+            .map(|&term| term.to_owned())
+            .collect::<Vec<_>>();
+        let doc_freqs = terms
+            .iter()
+            .map(|term| (term.clone(), index.doc_freq(term)))
+            .collect();
+        let mut hits = index.search(&terms, &index.stats(), &doc_freqs);
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.into_iter()
+            .map(|hit| {
+                (
+                    hit.relative_path.to_string_lossy().into_owned(),
+                    hit.page_idx,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_splits_words_and_cjk() {
+        assert_eq!(
+            tokenize("Hello, World! snake_case 42").collect::<Vec<_>>(),
+            ["hello", "world", "snake", "case", "42"]
+        );
+        assert_eq!(
+            tokenize("PDF文档abc").collect::<Vec<_>>(),
+            ["pdf", "文", "档", "abc"]
+        );
+        assert_eq!(tokenize(&"a".repeat(MAX_TERM_LEN + 1)).count(), 0);
+    }
+
+    #[test]
+    fn bm25_ranks_frequent_and_rare_terms() {
+        let mut index = PdfIndex::empty(PathBuf::new(), Path::new("/root"));
+        insert(&mut index, "a.pdf", &["rust rust rust pdf", "nothing here"]);
+        insert(
+            &mut index,
+            "b.pdf",
+            &["rust is one of many words on this rather long page"],
+        );
+        insert(&mut index, "c.pdf", &["unrelated pdf"]);
+
+        assert_eq!(index.stats().num_pages, 4);
+        assert_eq!(index.doc_freq("rust"), 2);
+        assert_eq!(
+            search(&index, &["rust"]),
+            [("a.pdf".to_owned(), 0), ("b.pdf".to_owned(), 0)]
+        );
+        // The rarer term outweighs the more common one
+        assert_eq!(
+            search(&index, &["unrelated", "rust"])[0],
+            ("c.pdf".to_owned(), 0)
+        );
+        assert!(search(&index, &["missing"]).is_empty());
+        assert!(idf(4.0, 4.0) > 0.0);
+    }
+
+    #[test]
+    fn reindexed_and_removed_files_are_forgotten() {
+        let mut index = PdfIndex::empty(PathBuf::new(), Path::new("/root"));
+        insert(&mut index, "a.pdf", &["rust"]);
+        insert(&mut index, "b.pdf", &["rust"]);
+        insert(&mut index, "a.pdf", &["python"]);
+        assert_eq!(index.doc_freq("rust"), 1);
+        assert_eq!(search(&index, &["rust"]), [("b.pdf".to_owned(), 0)]);
+        assert_eq!(search(&index, &["python"]), [("a.pdf".to_owned(), 0)]);
+
+        index.retain(|relative_path| relative_path != Path::new("b.pdf"));
+        assert_eq!(index.num_files(), 1);
+        assert!(search(&index, &["rust"]).is_empty());
+    }
+
+    #[test]
+    fn encrypted_files_have_no_postings() {
+        let mut index = PdfIndex::empty(PathBuf::new(), Path::new("/root"));
+        index.insert(
+            PathBuf::from("secret.pdf"),
+            IndexedFile::encrypted(&test_metadata()),
+        );
+        assert_eq!(
+            index.encrypted_files().collect::<Vec<_>>(),
+            [Path::new("secret.pdf")]
+        );
+        assert_eq!(index.stats().num_pages, 0);
+    }
+
+    #[tokio::test]
+    async fn segments_are_saved_merged_and_loaded() {
+        let dir =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-index-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = Path::new("/root");
+        let mut index = PdfIndex::empty(dir.clone(), root);
+        for i in 0..=MAX_SEGMENTS + 1 {
+            insert(
+                &mut index,
+                &format!("{i}.pdf"),
+                &[format!("common word{i}").as_str()],
+            );
+            index.save().await.unwrap();
+        }
+        // Indexed again, so its old postings are dead until the next merge
+        insert(&mut index, "0.pdf", &["replaced"]);
+        index.save().await.unwrap();
+        assert!(index.segments.len() <= MAX_SEGMENTS);
+
+        let loaded = PdfIndex::load(dir.clone(), root).await;
+        assert_eq!(loaded.num_files(), MAX_SEGMENTS + 2);
+        assert_eq!(loaded.doc_freq("common"), MAX_SEGMENTS + 1);
+        assert!(search(&loaded, &["word0"]).is_empty());
+        assert_eq!(search(&loaded, &["replaced"]), [("0.pdf".to_owned(), 0)]);
+        assert_eq!(search(&loaded, &["word1"]), [("1.pdf".to_owned(), 0)]);
+
+        // An index of another root directory is not reused
+        assert_eq!(
+            PdfIndex::load(dir.clone(), Path::new("/other"))
+                .await
+                .num_files(),
+            0
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn processes_sharing_a_directory_keep_each_others_files() {
+        let dir =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-shared-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = Path::new("/root");
+        let mut first = PdfIndex::load(dir.clone(), root).await;
+        let mut second = PdfIndex::load(dir.clone(), root).await;
+
+        insert(&mut first, "a.pdf", &["apple"]);
+        first.save().await.unwrap();
+        insert(&mut second, "b.pdf", &["banana"]);
+        second.save().await.unwrap();
+        // The second process picked up the first one’s file before saving its own
+        assert_eq!(search(&second, &["apple"]), [("a.pdf".to_owned(), 0)]);
+
+        insert(&mut first, "c.pdf", &["cherry"]);
+        first.retain(|relative_path| relative_path != Path::new("a.pdf"));
+        first.save().await.unwrap();
+
+        let loaded = PdfIndex::load(dir.clone(), root).await;
+        assert_eq!(loaded.num_files(), 2);
+        assert!(search(&loaded, &["apple"]).is_empty());
+        assert_eq!(search(&loaded, &["banana"]), [("b.pdf".to_owned(), 0)]);
+        assert_eq!(search(&loaded, &["cherry"]), [("c.pdf".to_owned(), 0)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
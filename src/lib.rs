@@ -0,0 +1,27 @@
+//! An MCP server for reading PDFs, coded by human, designed for AI.
+//!
+//! To mount the tools of pdflens inside another rmcp server, create a [`PdflensService`] for each
+//! session and forward `tools/list` and `tools/call` to its [`rmcp::ServerHandler`]
+//! implementation, as `examples/embed.rs` does. Use [`AccessPolicy::with_file_policy`] to decide
+//! the root directories of each session and how files are read.
+
+mod cache;
+mod index;
+mod logging;
+mod metadata;
+mod outline;
+pub mod param;
+mod policy;
+mod resource;
+mod search;
+mod service;
+mod watch;
+mod worker;
+mod workspace;
+
+pub use crate::cache::{LoadedPdf, PdfCache};
+pub use crate::index::PdfIndexStore;
+pub use crate::logging::McpLogLayer;
+pub use crate::policy::{AccessPolicy, ClientRootsMode, DefaultFilePolicy, FilePolicy};
+pub use crate::service::{AmbiguousPathError, PdfPasswordError, PdflensService, ToolConfig};
+pub use crate::worker::{PdfWorkers, WorkerConfig, WorkerLimits, run_pdf_worker};
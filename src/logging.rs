@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError, Weak};
+
+use rmcp::model::{LoggingLevel, LoggingMessageNotificationParam};
+use rmcp::{Peer, RoleServer};
+use tokio::sync::mpsc;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Span, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+const SESSION_SPAN_NAME: &str = "mcp_session";
+const SESSION_FIELD_NAME: &str = "session";
+
+// Sinks of the live sessions, looked up by the ID recorded in their spans
+static LOG_SINKS: LazyLock<Mutex<HashMap<u64, Weak<McpLogSink>>>> = LazyLock::new(Mutex::default);
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Sends the `tracing` events of one MCP session to its client as `notifications/message`.
+///
+/// Events are attributed to the session if they happen inside `span()`. Events inside
+/// `spawn_blocking` have no parent span, so they only go to stderr, unless the closure enters
+/// the span of its caller.
+pub struct McpLogSink {
+    id: u64,
+    span: Span,
+    min_severity: AtomicU8,
+    // Set once the client finished initialization, as no notifications may be sent before
+    tx: Mutex<Option<mpsc::UnboundedSender<LoggingMessageNotificationParam>>>,
+}
+
+/// Attaches the events of `McpLogSink::span()` to their session.
+pub struct McpLogLayer;
+
+struct SessionId(u64);
+
+impl McpLogSink {
+    pub fn new() -> Arc<Self> {
+        let id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let sink = Arc::new(Self {
+            id,
+            span: tracing::info_span!(parent: None, SESSION_SPAN_NAME, session = id),
+            // Until the client calls `logging/setLevel`
+            min_severity: AtomicU8::new(severity(LoggingLevel::Warning)),
+            tx: Mutex::default(),
+        });
+        LOG_SINKS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, Arc::downgrade(&sink));
+        sink
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn set_level(&self, level: LoggingLevel) {
+        self.min_severity.store(severity(level), Ordering::Relaxed);
+    }
+
+    /// Starts sending log messages to the client. Messages are queued, so they arrive in order.
+    pub fn connect(&self, peer: Peer<RoleServer>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(param) = rx.recv().await {
+                if peer.notify_logging_message(param).await.is_err() {
+                    // The session has ended
+                    break;
+                }
+            }
+        });
+        *self.tx.lock().unwrap_or_else(PoisonError::into_inner) = Some(tx);
+    }
+
+    fn send(&self, event: &Event<'_>) {
+        let level = match *event.metadata().level() {
+            Level::ERROR => LoggingLevel::Error,
+            Level::WARN => LoggingLevel::Warning,
+            Level::INFO => LoggingLevel::Info,
+            Level::DEBUG | Level::TRACE => LoggingLevel::Debug,
+        };
+        if severity(level) < self.min_severity.load(Ordering::Relaxed) {
+            return;
+        }
+        let tx = self.tx.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(tx) = &*tx else {
+            return;
+        };
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        // Only fails after the session has ended
+        let _ = tx.send(LoggingMessageNotificationParam {
+            level,
+            logger: Some(event.metadata().target().to_owned()),
+            data: serde_json::Value::String(visitor.0),
+        });
+    }
+}
+
+impl Drop for McpLogSink {
+    fn drop(&mut self) {
+        LOG_SINKS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+impl<S> Layer<S> for McpLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != SESSION_SPAN_NAME {
+            return;
+        }
+        let mut visitor = SessionIdVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(session_id) = visitor.0
+            && let Some(span) = ctx.span(id)
+        {
+            span.extensions_mut().insert(SessionId(session_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(session_id) = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<SessionId>().map(|id| id.0))
+        }) else {
+            return;
+        };
+        // Released before the sink, which may be the last reference and lock it again on drop
+        let sink = LOG_SINKS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&session_id)
+            .and_then(Weak::upgrade);
+        if let Some(sink) = sink {
+            sink.send(event);
+        }
+    }
+}
+
+#[derive(Default)]
+struct SessionIdVisitor(Option<u64>);
+
+impl Visit for SessionIdVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == SESSION_FIELD_NAME {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Formats an event like the stderr output does, e.g. `Failed to parse PDF key=value`.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.record_debug(field, &format_args!("{value}"));
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
+
+fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    // Like `connect`, without a client
+    fn receive_messages(
+        sink: &McpLogSink,
+    ) -> mpsc::UnboundedReceiver<LoggingMessageNotificationParam> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        *sink.tx.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    fn messages(
+        rx: &mut mpsc::UnboundedReceiver<LoggingMessageNotificationParam>,
+    ) -> Vec<(LoggingLevel, String)> {
+        let mut messages = Vec::new();
+        while let Ok(param) = rx.try_recv() {
+            messages.push((param.level, param.data.as_str().unwrap().to_owned()));
+        }
+        messages
+    }
+
+    #[test]
+    fn sends_events_to_their_session() {
+        let subscriber = tracing_subscriber::registry().with(McpLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let first = McpLogSink::new();
+            let second = McpLogSink::new();
+            first
+                .span()
+                .in_scope(|| tracing::warn!("Before connecting"));
+            let mut first_rx = receive_messages(&first);
+            let mut second_rx = receive_messages(&second);
+
+            first.span().in_scope(|| {
+                tracing::info!("Below the default level");
+                tracing::info_span!("nested").in_scope(|| tracing::warn!("First"));
+            });
+            second.span().in_scope(|| tracing::error!("Second"));
+            tracing::error!("Outside any session");
+            assert_eq!(
+                messages(&mut first_rx),
+                [(LoggingLevel::Warning, "First".to_owned())]
+            );
+            assert_eq!(
+                messages(&mut second_rx),
+                [(LoggingLevel::Error, "Second".to_owned())]
+            );
+
+            // Dropped sinks are forgotten
+            let second_id = second.id;
+            drop(second);
+            assert!(!LOG_SINKS.lock().unwrap().contains_key(&second_id));
+        });
+    }
+
+    #[test]
+    fn changes_the_level_of_one_session() {
+        let subscriber = tracing_subscriber::registry().with(McpLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let first = McpLogSink::new();
+            let second = McpLogSink::new();
+            let mut first_rx = receive_messages(&first);
+            let mut second_rx = receive_messages(&second);
+            first.set_level(LoggingLevel::Debug);
+            second.set_level(LoggingLevel::Error);
+
+            for sink in [&first, &second] {
+                sink.span().in_scope(|| {
+                    tracing::trace!("Trace");
+                    tracing::info!("Info");
+                    tracing::warn!("Warning");
+                });
+            }
+            assert_eq!(
+                messages(&mut first_rx),
+                [
+                    (LoggingLevel::Debug, "Trace".to_owned()),
+                    (LoggingLevel::Info, "Info".to_owned()),
+                    (LoggingLevel::Warning, "Warning".to_owned()),
+                ]
+            );
+            assert!(messages(&mut second_rx).is_empty());
+
+            // Levels without a `tracing` equivalent round up
+            first.set_level(LoggingLevel::Notice);
+            first.span().in_scope(|| {
+                tracing::info!("Info");
+                tracing::warn!("Warning");
+            });
+            assert_eq!(
+                messages(&mut first_rx),
+                [(LoggingLevel::Warning, "Warning".to_owned())]
+            );
+        });
+    }
+
+    #[test]
+    fn formats_fields_like_stderr() {
+        let subscriber = tracing_subscriber::registry().with(McpLogLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            let sink = McpLogSink::new();
+            let mut rx = receive_messages(&sink);
+            sink.span().in_scope(|| {
+                tracing::warn!(path = "a.pdf", page = 2, reason = %"broken", "Failed to {}", "parse");
+                tracing::warn!(path = "a.pdf");
+            });
+            let (_, messages): (Vec<_>, Vec<_>) = messages(&mut rx).into_iter().unzip();
+            assert_eq!(
+                messages,
+                [
+                    r#"Failed to parse path="a.pdf" page=2 reason=broken"#,
+                    r#"path="a.pdf""#,
+                ]
+            );
+        });
+    }
+}
@@ -1,40 +1,104 @@
-mod param;
-mod service;
+mod command;
+mod config;
+mod http;
 
-use crate::service::PdflensService;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::command::Command;
+use crate::config::{Cli, Config};
+use clap::Parser;
 use eyre::Result;
+use pdflens_mcp::{McpLogLayer, PdfCache, PdfIndexStore, PdfWorkers, PdflensService, WorkerConfig};
 use rmcp::ServiceExt;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
 use tracing_subscriber::{EnvFilter, prelude::*};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let mut cli = Cli::parse();
+    let command = cli.command.take();
     color_eyre::config::HookBuilder::new()
         .theme(color_eyre::config::Theme::new()) // Disable colors to avoid messing up MCP clients’ logs
         .install()?;
-    tracing_subscriber::registry()
-        .with(tracing_error::ErrorLayer::default())
-        .with({
-            let mut filter = EnvFilter::new(concat!("warn,", env!("CARGO_CRATE_NAME"), "=info"));
-            if let Some(env) = std::env::var_os(EnvFilter::DEFAULT_ENV) {
-                for segment in env.to_string_lossy().split(',') {
-                    if let Ok(directive) = segment.parse() {
-                        filter = filter.add_directive(directive);
-                    }
+    let env_filter = || {
+        let mut filter = EnvFilter::new(concat!("warn,", env!("CARGO_CRATE_NAME"), "=info"));
+        if let Some(env) = std::env::var_os(EnvFilter::DEFAULT_ENV) {
+            for segment in env.to_string_lossy().split(',') {
+                if let Ok(directive) = segment.parse() {
+                    filter = filter.add_directive(directive);
                 }
             }
-            filter
-        })
+        }
+        filter
+    };
+    tracing_subscriber::registry()
+        .with(tracing_error::ErrorLayer::default().with_filter(env_filter()))
         .with(
             tracing_subscriber::fmt::layer()
                 .with_ansi(false)
-                .with_writer(std::io::stderr),
+                .with_writer(std::io::stderr)
+                .with_filter(env_filter()),
+        )
+        // MCP clients choose their own log level with `logging/setLevel`
+        .with(
+            McpLogLayer
+                .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::DEBUG)),
         )
         .init();
 
-    let service = PdflensService::new()
-        .serve(rmcp::transport::stdio())
-        .await?;
-    service.waiting().await?;
+    if let Some(Command::Worker(limits)) = command {
+        return pdflens_mcp::run_pdf_worker(limits);
+    }
+
+    let config = Config::load(cli, &PdflensService::tool_names(), command.is_some())?;
+    let pdf_cache = Arc::new(PdfCache::new(
+        config.cache_size_mib.saturating_mul(1024 * 1024),
+    ));
+    let pdf_index = Arc::new(PdfIndexStore::new(config.index_dir));
+    let access_policy = Arc::new(config.access_policy);
+    let tool_config = Arc::new(config.tool_config);
+    let pdf_workers = Arc::new(PdfWorkers::new(WorkerConfig {
+        program: std::env::current_exe()?,
+        args: vec![
+            "worker".to_owned(),
+            "--max-memory-mib".to_owned(),
+            config.worker_limits.max_memory_mib.to_string(),
+            "--max-cpu-secs".to_owned(),
+            config.worker_limits.max_cpu_secs.to_string(),
+        ],
+        isolate_text: config.isolate_text,
+        isolate_rendering: config.isolate_rendering,
+        // Leave room for the time spent waiting for a CPU, unlike the CPU time limit
+        timeout: (config.worker_limits.max_cpu_secs != 0)
+            .then(|| Duration::from_secs(config.worker_limits.max_cpu_secs.saturating_mul(2))),
+    }));
+    if config.http.is_some() && access_policy.roots.is_empty() {
+        tracing::warn!(
+            "No root directories are configured. HTTP clients without root directories can read PDFs in the current directory."
+        );
+    }
+    let new_service = move || {
+        PdflensService::new(
+            pdf_cache.clone(),
+            pdf_index.clone(),
+            access_policy.clone(),
+            tool_config.clone(),
+        )
+        .with_pdf_workers(pdf_workers.clone())
+    };
+
+    if let Some(command) = command {
+        return command.run(new_service()).await;
+    }
+
+    if let Some(http_config) = config.http {
+        http::serve(http_config, new_service).await?;
+    } else {
+        let service = new_service().serve(rmcp::transport::stdio()).await?;
+        service.waiting().await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,423 @@
+use std::borrow::Cow;
+
+use hayro::hayro_syntax::Pdf;
+use hayro::hayro_syntax::metadata::DateTime;
+use hayro::hayro_syntax::object::dict::keys;
+use hayro::hayro_syntax::object::{Dict, Name, Stream};
+
+use crate::param::{GetPdfMetadataResult, PdfInfo, PdfXmp};
+
+/// Only the first 1024 bytes may contain the header and the linearization dictionary.
+const HEADER_SEARCH_LEN: usize = 1024;
+/// `startxref` must be in the last 1024 bytes, but some writers append a little more.
+const TRAILER_SEARCH_LEN: usize = 2048;
+
+#[rustfmt::skip]
+const PDF_DOC_ENCODING_18_1F: [char; 8] = [
+    '\u{02d8}', '\u{02c7}', '\u{02c6}', '\u{02d9}', '\u{02dd}', '\u{02db}', '\u{02da}', '\u{02dc}',
+];
+
+#[rustfmt::skip]
+const PDF_DOC_ENCODING_80_A0: [char; 33] = [
+    '\u{2022}', '\u{2020}', '\u{2021}', '\u{2026}', '\u{2014}', '\u{2013}', '\u{0192}', '\u{2044}',
+    '\u{2039}', '\u{203a}', '\u{2212}', '\u{2030}', '\u{201e}', '\u{201c}', '\u{201d}', '\u{2018}',
+    '\u{2019}', '\u{201a}', '\u{2122}', '\u{fb01}', '\u{fb02}', '\u{0141}', '\u{0152}', '\u{0160}',
+    '\u{0178}', '\u{017d}', '\u{0131}', '\u{0142}', '\u{0153}', '\u{0161}', '\u{017e}', '\u{fffd}',
+    '\u{20ac}',
+];
+
+/// `password_required` tells whether a password was needed to open the PDF.
+pub fn read_metadata(
+    pdf: &Pdf,
+    password_required: bool,
+    include_xmp_packet: bool,
+) -> GetPdfMetadataResult {
+    let data: &[u8] = pdf.data().as_ref().as_ref();
+    let header = &data[..data.len().min(HEADER_SEARCH_LEN)];
+    let catalog = catalog(pdf);
+
+    let header_version = find_bytes(header, b"%PDF-").and_then(|idx| {
+        let version = &header[idx + 5..];
+        let len = version
+            .iter()
+            .take_while(|&&c| c.is_ascii_digit() || c == b'.')
+            .count();
+        std::str::from_utf8(&version[..len]).ok().map(str::to_owned)
+    });
+    // The catalog may override the header with a later version
+    let catalog_version = catalog
+        .as_ref()
+        .and_then(|catalog| catalog.get::<Name>(keys::VERSION))
+        .map(|version| String::from_utf8_lossy(version.as_ref()).into_owned());
+    // Compared as numbers, so that “1.10” would be later than “1.9”
+    let pdf_version = header_version
+        .into_iter()
+        .chain(catalog_version)
+        .max_by_key(|version| parse_version(version));
+
+    let tagged = catalog
+        .as_ref()
+        .and_then(|catalog| catalog.get::<Dict>(keys::MARK_INFO))
+        .and_then(|mark_info| mark_info.get::<bool>(keys::MARKED))
+        .unwrap_or_default();
+
+    let xmp_packet = catalog
+        .as_ref()
+        .and_then(|catalog| catalog.get::<Stream>(keys::METADATA))
+        .and_then(|stream| stream.decoded().ok())
+        .map(|packet| String::from_utf8_lossy(&packet).into_owned());
+
+    let metadata = pdf.metadata();
+    let info = PdfInfo {
+        title: metadata.title.as_deref().map(decode_text_string),
+        author: metadata.author.as_deref().map(decode_text_string),
+        subject: metadata.subject.as_deref().map(decode_text_string),
+        keywords: metadata.keywords.as_deref().map(decode_text_string),
+        creator: metadata.creator.as_deref().map(decode_text_string),
+        producer: metadata.producer.as_deref().map(decode_text_string),
+        creation_date: metadata.creation_date.as_ref().map(format_date),
+        modification_date: metadata.modification_date.as_ref().map(format_date),
+    };
+
+    GetPdfMetadataResult {
+        num_pages: pdf.pages().len(),
+        pdf_version,
+        encrypted: password_required || has_encrypt_entry(data),
+        password_required,
+        tagged,
+        linearized: find_bytes(header, b"/Linearized").is_some(),
+        info,
+        xmp: xmp_packet.map(|packet| parse_xmp(packet, include_xmp_packet)),
+    }
+}
+
+/// Parses a version like “1.7” into its major and minor numbers.
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let (major, minor) = version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Whether the trailer of the last cross-reference section has an `/Encrypt` entry. A PDF may be
+/// encrypted without needing a password to open it, e.g. with only an owner password.
+fn has_encrypt_entry(data: &[u8]) -> bool {
+    let tail = &data[data.len().saturating_sub(TRAILER_SEARCH_LEN)..];
+    let section = rfind_bytes(tail, b"startxref")
+        .and_then(|idx| {
+            let offset = tail[idx + b"startxref".len()..].trim_ascii_start();
+            let len = offset.iter().take_while(|c| c.is_ascii_digit()).count();
+            std::str::from_utf8(&offset[..len])
+                .ok()?
+                .parse::<usize>()
+                .ok()
+        })
+        .and_then(|offset| data.get(offset..));
+    let trailer = match section {
+        // A cross-reference table is followed by the trailer dictionary
+        Some(section) if section.starts_with(b"xref") => {
+            find_bytes(section, b"trailer").map(|idx| &section[idx..])
+        }
+        // A cross-reference stream keeps the trailer entries in its own dictionary
+        Some(section) => Some(section),
+        // The offset is broken, so only the last trailer can be found
+        None => rfind_bytes(tail, b"trailer").map(|idx| &tail[idx..]),
+    };
+    let Some(trailer) = trailer else {
+        return false;
+    };
+    let end = [&b"stream"[..], b"startxref"]
+        .into_iter()
+        .filter_map(|keyword| find_bytes(trailer, keyword))
+        .min()
+        .unwrap_or(trailer.len());
+    let trailer = &trailer[..end];
+    let key = b"/Encrypt";
+    (0..trailer.len()).any(|idx| {
+        trailer[idx..].starts_with(key)
+            && !trailer
+                .get(idx + key.len())
+                .is_some_and(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+pub fn catalog(pdf: &Pdf) -> Option<Dict<'_>> {
+    let xref = pdf.xref();
+    xref.get::<Dict>(xref.trailer_data().root_ref)
+}
+
+/// Decodes a PDF text string, which is either UTF-16BE, UTF-8, or PDFDocEncoding.
+pub fn decode_text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(b"\xfe\xff") {
+        char::decode_utf16(
+            utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+    } else if let Some(utf8) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        String::from_utf8_lossy(utf8).into_owned()
+    } else {
+        bytes
+            .iter()
+            .map(|&c| match c {
+                0x18..=0x1f => PDF_DOC_ENCODING_18_1F[usize::from(c - 0x18)],
+                0x80..=0xa0 => PDF_DOC_ENCODING_80_A0[usize::from(c - 0x80)],
+                _ => char::from(c),
+            })
+            .collect()
+    }
+}
+
+fn format_date(date: &DateTime) -> String {
+    let sign = if date.utc_offset_hour < 0 { '-' } else { '+' };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{sign}{:02}:{:02}",
+        date.year,
+        date.month,
+        date.day,
+        date.hour,
+        date.minute,
+        date.second,
+        date.utc_offset_hour.unsigned_abs(),
+        date.utc_offset_minute,
+    )
+}
+
+fn parse_xmp(packet: String, include_packet: bool) -> PdfXmp {
+    let first = |name| xmp_property(&packet, name).into_iter().next();
+    PdfXmp {
+        title: first("dc:title"),
+        creators: xmp_property(&packet, "dc:creator"),
+        description: first("dc:description"),
+        subjects: xmp_property(&packet, "dc:subject"),
+        keywords: first("pdf:Keywords"),
+        creator_tool: first("xmp:CreatorTool"),
+        producer: first("pdf:Producer"),
+        create_date: first("xmp:CreateDate"),
+        modify_date: first("xmp:ModifyDate"),
+        metadata_date: first("xmp:MetadataDate"),
+        pdfa_part: first("pdfaid:part"),
+        pdfa_conformance: first("pdfaid:conformance"),
+        packet: include_packet.then_some(packet),
+    }
+}
+
+/// A minimal XMP reader. It understands simple properties in both element and attribute forms,
+/// and collects the items of `rdf:Alt`, `rdf:Bag`, and `rdf:Seq` containers.
+fn xmp_property(packet: &str, name: &str) -> Vec<String> {
+    // Element form, e.g. `<dc:title><rdf:Alt><rdf:li xml:lang="x-default">…</rdf:li></rdf:Alt></dc:title>`
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+    let mut search_from = 0;
+    while let Some(idx) = packet[search_from..].find(&open) {
+        let rest = &packet[search_from + idx + open.len()..];
+        search_from += idx + open.len();
+        if !rest.starts_with(|c: char| c == '>' || c.is_ascii_whitespace()) {
+            // Only a prefix of another property name, e.g. `dc:creator` vs `dc:creatorX`
+            continue;
+        }
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        if rest[..tag_end].ends_with('/') {
+            continue;
+        }
+        let body = &rest[tag_end + 1..];
+        let Some(body_end) = body.find(&close) else {
+            break;
+        };
+        let body = &body[..body_end];
+        if !body.contains("<rdf:li") {
+            return vec![unescape_xml(body.trim()).into_owned()];
+        }
+        return body
+            .split("<rdf:li")
+            .skip(1)
+            .filter_map(|item| {
+                let item = &item[item.find('>')? + 1..];
+                let item = &item[..item.find("</rdf:li>")?];
+                Some(unescape_xml(item.trim()).into_owned())
+            })
+            .collect();
+    }
+
+    // Attribute form, e.g. `<rdf:Description pdf:Producer="…">`
+    let attribute = format!("{name}=");
+    for (idx, _) in packet.match_indices(&attribute) {
+        if !packet[..idx].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let value = &packet[idx + attribute.len()..];
+        let Some(quote) = value.chars().next().filter(|&c| c == '"' || c == '\'') else {
+            continue;
+        };
+        let value = &value[1..];
+        if let Some(value_end) = value.find(quote) {
+            return vec![unescape_xml(&value[..value_end]).into_owned()];
+        }
+    }
+
+    Vec::new()
+}
+
+fn unescape_xml(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut builder = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find('&') {
+        builder.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        let Some(entity_end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..entity_end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse::<u32>))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        if let Some(c) = c {
+            builder.push(c);
+            rest = &rest[entity_end + 1..];
+        } else {
+            builder.push('&');
+            rest = &rest[1..];
+        }
+    }
+    builder.push_str(rest);
+    Cow::Owned(builder)
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::test_pdf_data;
+
+    fn with_trailer_entry(entry: &str) -> Vec<u8> {
+        let data = String::from_utf8(test_pdf_data(&["Page"])).unwrap();
+        data.replace("trailer\n<< ", &format!("trailer\n<< {entry} "))
+            .into_bytes()
+    }
+
+    #[test]
+    fn finds_encrypt_entry_in_trailer() {
+        assert!(!has_encrypt_entry(&test_pdf_data(&["Page"])));
+        assert!(has_encrypt_entry(&with_trailer_entry("/Encrypt 9 0 R")));
+        assert!(has_encrypt_entry(&with_trailer_entry(
+            "/Encrypt << /Filter /Standard /EncryptMetadata false >>"
+        )));
+        assert!(!has_encrypt_entry(&with_trailer_entry(
+            "/EncryptMetadata false"
+        )));
+    }
+
+    #[test]
+    fn finds_encrypt_entry_in_xref_stream() {
+        let data = b"%PDF-1.5\n1 0 obj\n<< /Type /XRef /Encrypt 2 0 R /Size 3 >>\nstream\n/Encrypt\nendstream\nendobj\nstartxref\n9\n%%EOF\n";
+        assert!(has_encrypt_entry(data));
+        let data = b"%PDF-1.5\n1 0 obj\n<< /Type /XRef /Size 3 >>\nstream\n/Encrypt\nendstream\nendobj\nstartxref\n9\n%%EOF\n";
+        assert!(!has_encrypt_entry(data));
+    }
+
+    #[test]
+    fn parses_versions_as_numbers() {
+        assert_eq!(parse_version("1.7"), Some((1, 7)));
+        assert_eq!(parse_version("2.0"), Some((2, 0)));
+        assert!(parse_version("1.10") > parse_version("1.9"));
+        assert_eq!(parse_version("1"), None);
+        assert_eq!(parse_version("a.b"), None);
+    }
+
+    #[test]
+    fn decodes_text_strings() {
+        assert_eq!(decode_text_string(b"\xfe\xff\x00H\x00i\x4e\x2d"), "Hi中");
+        // A surrogate pair, and an unpaired surrogate
+        assert_eq!(decode_text_string(b"\xfe\xff\xd8\x3d\xde\x00"), "😀");
+        assert_eq!(decode_text_string(b"\xfe\xff\xd8\x3d"), "\u{fffd}");
+        assert_eq!(decode_text_string(b"\xef\xbb\xbfcaf\xc3\xa9"), "café");
+        // PDFDocEncoding differs from Latin-1 in a few ranges
+        assert_eq!(decode_text_string(b"caf\xe9"), "café");
+        assert_eq!(
+            decode_text_string(b"\x8dquoted\x8e"),
+            "\u{201c}quoted\u{201d}"
+        );
+        assert_eq!(
+            decode_text_string(b"\x80 \xa0 \x18"),
+            "\u{2022} \u{20ac} \u{02d8}"
+        );
+    }
+
+    #[test]
+    fn unescapes_xml() {
+        assert!(matches!(unescape_xml("plain"), Cow::Borrowed("plain")));
+        assert_eq!(
+            unescape_xml("a &lt;b&gt; &amp; &quot;c&quot; &apos;d&apos;"),
+            "a <b> & \"c\" 'd'"
+        );
+        assert_eq!(unescape_xml("&#65;&#x42;&#x1F600;"), "AB😀");
+        // Unknown or unterminated entities are kept
+        assert_eq!(unescape_xml("&nbsp; &#xZZ; & x"), "&nbsp; &#xZZ; & x");
+        assert_eq!(unescape_xml("a & b"), "a & b");
+    }
+
+    #[test]
+    fn reads_xmp_properties() {
+        let packet = r#"<x:xmpmeta><rdf:RDF>
+            <rdf:Description pdf:Producer="Writer &amp; Co" xmp:CreateDate='2024-01-31T12:00:00Z'>
+              <dc:title><rdf:Alt><rdf:li xml:lang="x-default"> A &lt;Title&gt; </rdf:li></rdf:Alt></dc:title>
+              <dc:creatorTool>Not a creator</dc:creatorTool>
+              <dc:creator><rdf:Seq><rdf:li>Alice</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></dc:creator>
+              <dc:description/>
+              <pdf:Keywords>pdf, xmp</pdf:Keywords>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        assert_eq!(xmp_property(packet, "dc:title"), ["A <Title>"]);
+        assert_eq!(xmp_property(packet, "dc:creator"), ["Alice", "Bob"]);
+        assert_eq!(xmp_property(packet, "pdf:Keywords"), ["pdf, xmp"]);
+        assert_eq!(xmp_property(packet, "pdf:Producer"), ["Writer & Co"]);
+        assert_eq!(
+            xmp_property(packet, "xmp:CreateDate"),
+            ["2024-01-31T12:00:00Z"]
+        );
+        assert!(xmp_property(packet, "dc:description").is_empty());
+        assert!(xmp_property(packet, "dc:subject").is_empty());
+
+        let xmp = parse_xmp(packet.to_owned(), false);
+        assert_eq!(xmp.title.as_deref(), Some("A <Title>"));
+        assert_eq!(xmp.creators, ["Alice", "Bob"]);
+        assert!(xmp.packet.is_none());
+    }
+
+    #[test]
+    fn reads_metadata_of_unencrypted_pdf() {
+        let pdf = crate::cache::tests::test_pdf(&["One", "Two"]);
+        let metadata = read_metadata(&pdf, false, false);
+        assert_eq!(metadata.num_pages, 2);
+        assert_eq!(metadata.pdf_version.as_deref(), Some("1.4"));
+        assert!(!metadata.encrypted);
+        assert!(!metadata.password_required);
+        assert!(!metadata.linearized);
+        assert!(metadata.xmp.is_none());
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use eyre::{Result, bail};
+
+use hayro::hayro_syntax::Pdf;
+use hayro::hayro_syntax::object::dict::keys;
+use hayro::hayro_syntax::object::{
+    Array, Dict, Name, Object, ObjectIdentifier, String as PdfString,
+};
+
+use crate::metadata::{catalog, decode_text_string};
+use crate::param::PdfOutlineItem;
+
+// Malformed PDFs may contain cyclic or absurdly deep structures
+const MAX_DEPTH: usize = 64;
+const MAX_OUTLINE_ITEMS: usize = 10000;
+// Destinations in the top margin and running headers still count as the top of the page
+const PAGE_TOP_RATIO: f64 = 0.2;
+
+struct OutlineReader<'a> {
+    catalog: Dict<'a>,
+    page_indices: HashMap<ObjectIdentifier, usize>,
+    // Bottom and top coordinates of the crop box of each page
+    page_bounds: Vec<(f64, f64)>,
+    visited: HashSet<ObjectIdentifier>,
+    items: Vec<PdfOutlineItem>,
+}
+
+/// Returns the outline items in document order, flattened with their nesting levels.
+pub fn read_outline(pdf: &Pdf) -> Vec<PdfOutlineItem> {
+    let Some(catalog) = catalog(pdf) else {
+        return Vec::new();
+    };
+    let Some(first) = catalog
+        .get::<Dict>(keys::OUTLINES)
+        .and_then(|outlines| outlines.get::<Dict>(keys::FIRST))
+    else {
+        return Vec::new();
+    };
+
+    let page_indices = pdf
+        .pages()
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, page)| Some((page.raw().obj_id()?, idx)))
+        .collect();
+    let page_bounds = pdf
+        .pages()
+        .iter()
+        .map(|page| {
+            let crop_box = page.crop_box();
+            (crop_box.y0, crop_box.y1)
+        })
+        .collect();
+    let mut reader = OutlineReader {
+        catalog,
+        page_indices,
+        page_bounds,
+        visited: HashSet::new(),
+        items: Vec::new(),
+    };
+    reader.read_siblings(first, 1);
+    reader.items
+}
+
+impl<'a> OutlineReader<'a> {
+    fn read_siblings(&mut self, first: Dict<'a>, level: usize) {
+        if level > MAX_DEPTH {
+            return;
+        }
+        let mut item = Some(first);
+        while let Some(current) = item {
+            if self.items.len() >= MAX_OUTLINE_ITEMS {
+                return;
+            }
+            if let Some(id) = current.obj_id()
+                && !self.visited.insert(id)
+            {
+                return;
+            }
+
+            let title = current
+                .get::<PdfString>(keys::TITLE)
+                .map(|title| decode_text_string(&title.get()))
+                .unwrap_or_default();
+            let dest = current.get::<Object>(keys::DEST).or_else(|| {
+                // Only `GoTo` actions jump within the same document
+                let action = current.get::<Dict>(keys::A)?;
+                if action.get::<Name>(keys::S)?.as_ref() != b"GoTo" {
+                    return None;
+                }
+                action.get::<Object>(keys::D)
+            });
+            let target = dest.and_then(|dest| self.resolve_dest(dest, 0));
+            self.items.push(PdfOutlineItem {
+                title,
+                level,
+                page: target.map(|(idx, _)| idx + 1),
+                below_top: target.is_some_and(|(_, below_top)| below_top),
+            });
+
+            if let Some(child) = current.get::<Dict>(keys::FIRST) {
+                self.read_siblings(child, level + 1);
+            }
+            item = current.get::<Dict>(keys::NEXT);
+        }
+    }
+
+    /// Returns the 0-based page index of a destination, and whether it is below the top of the
+    /// page.
+    fn resolve_dest(&self, dest: Object<'a>, depth: usize) -> Option<(usize, bool)> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match dest {
+            Object::Array(array) => self.resolve_explicit_dest(&array),
+            Object::Name(name) => self.resolve_named_dest(name.as_ref(), depth),
+            Object::String(name) => self.resolve_named_dest(&name.get(), depth),
+            // Values in the `Dests` dictionary may be wrapped in a dictionary with a `D` entry
+            Object::Dict(dict) => self.resolve_dest(dict.get::<Object>(keys::D)?, depth + 1),
+            _ => None,
+        }
+    }
+
+    fn resolve_explicit_dest(&self, array: &Array<'a>) -> Option<(usize, bool)> {
+        let page_idx = match array.get::<Dict>(0) {
+            Some(page) => self.page_indices.get(&page.obj_id()?).copied()?,
+            // Some producers write a page index instead of a page reference
+            None => array.get::<usize>(0)?,
+        };
+        // Other kinds of destinations show the whole page, or keep the current position
+        let top = array.get::<Name>(1).and_then(|kind| match kind.as_ref() {
+            b"XYZ" => array.get::<f64>(3),
+            b"FitH" | b"FitBH" => array.get::<f64>(2),
+            b"FitR" => array.get::<f64>(5),
+            _ => None,
+        });
+        let below_top = match (top, self.page_bounds.get(page_idx)) {
+            (Some(top), Some(&(bottom, page_top))) => {
+                top < page_top - (page_top - bottom) * PAGE_TOP_RATIO
+            }
+            _ => false,
+        };
+        Some((page_idx, below_top))
+    }
+
+    fn resolve_named_dest(&self, name: &[u8], depth: usize) -> Option<(usize, bool)> {
+        // PDF 1.1 stores named destinations in a dictionary
+        if let Some(dest) = self
+            .catalog
+            .get::<Dict>(keys::DESTS)
+            .and_then(|dests| dests.get::<Object>(name))
+        {
+            return self.resolve_dest(dest, depth + 1);
+        }
+        // PDF 1.2 and later store named destinations in a name tree
+        let tree = self
+            .catalog
+            .get::<Dict>(keys::NAMES)?
+            .get::<Dict>(keys::DESTS)?;
+        let dest = lookup_name_tree(&tree, name, 0)?;
+        self.resolve_dest(dest, depth + 1)
+    }
+}
+
+fn lookup_name_tree<'a>(node: &Dict<'a>, name: &[u8], depth: usize) -> Option<Object<'a>> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    if let Some(names) = node.get::<Array>(keys::NAMES) {
+        let mut entries = names.iter::<Object>();
+        while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+            if let Object::String(key) = key
+                && key.get().as_ref() == name
+            {
+                return Some(value);
+            }
+        }
+    }
+    if let Some(kids) = node.get::<Array>(keys::KIDS) {
+        for kid in kids.iter::<Dict>() {
+            if let Some(limits) = kid.get::<Array>(keys::LIMITS)
+                && let (Some(lower), Some(upper)) =
+                    (limits.get::<PdfString>(0), limits.get::<PdfString>(1))
+                && (name < lower.get().as_ref() || name > upper.get().as_ref())
+            {
+                continue;
+            }
+            if let Some(value) = lookup_name_tree(&kid, name, depth + 1) {
+                return Some(value);
+            }
+        }
+    }
+    None
+}
+
+/// Finds an outline item by its title, or by a path of titles separated by “>”.
+pub fn find_section(items: &[PdfOutlineItem], section: &str) -> Result<usize> {
+    let path = section
+        .split('>')
+        .map(normalize_title)
+        .filter(|title| !title.is_empty())
+        .collect::<Vec<_>>();
+    let Some((title, ancestor_titles)) = path.split_last() else {
+        bail!("The section title is empty.");
+    };
+
+    let candidates = items
+        .iter()
+        .enumerate()
+        .filter(|(idx, item)| {
+            if normalize_title(&item.title) != *title {
+                return false;
+            }
+            // Each parent title in the path should match an ancestor, but levels may be skipped
+            let mut remaining_ancestors = ancestors(items, *idx);
+            ancestor_titles.iter().rev().all(|ancestor_title| {
+                remaining_ancestors
+                    .any(|ancestor| normalize_title(&items[ancestor].title) == *ancestor_title)
+            })
+        })
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+
+    match candidates.as_slice() {
+        [] => {
+            bail!("Section not found: {section:?}\nPlease check the titles with `get_pdf_outline`.")
+        }
+        [idx, rest @ ..]
+            if rest
+                .iter()
+                .all(|other| items[*other].page == items[*idx].page) =>
+        {
+            Ok(*idx)
+        }
+        _ => bail!(
+            "Section title is ambiguous: {section:?}\nPlease use one of the following instead:\n{}",
+            candidates
+                .iter()
+                .map(|&idx| format!("* {:?}", section_path(items, idx)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+/// Returns the 0-based, half-closed half-open page indices of a section.
+///
+/// The section ends before the page where the next item at the same or a higher level starts.
+/// That page is only included if the next item is below the top of it, because the section then
+/// continues above the next title.
+pub fn section_page_range(
+    items: &[PdfOutlineItem],
+    idx: usize,
+    num_pages: usize,
+) -> Option<Range<usize>> {
+    let item = &items[idx];
+    let from_page_idx = item.page?.checked_sub(1).filter(|&x| x < num_pages)?;
+    let to_page_idx = items[idx + 1..]
+        .iter()
+        .filter(|next| next.level <= item.level)
+        .find_map(|next| {
+            let page_idx = next.page?.checked_sub(1)?;
+            Some(if next.below_top {
+                page_idx + 1
+            } else {
+                page_idx
+            })
+        })
+        .map(|x| x.clamp(from_page_idx + 1, num_pages))
+        .unwrap_or(num_pages);
+    Some(from_page_idx..to_page_idx)
+}
+
+/// Returns the indices of the ancestors of an outline item, from the nearest to the farthest.
+fn ancestors(items: &[PdfOutlineItem], idx: usize) -> impl Iterator<Item = usize> {
+    let mut level = items[idx].level;
+    (0..idx).rev().filter(move |&ancestor| {
+        if items[ancestor].level < level {
+            level = items[ancestor].level;
+            true
+        } else {
+            false
+        }
+    })
+}
+
+fn section_path(items: &[PdfOutlineItem], idx: usize) -> String {
+    let mut titles = ancestors(items, idx)
+        .map(|ancestor| items[ancestor].title.trim())
+        .collect::<Vec<_>>();
+    titles.reverse();
+    titles.push(items[idx].title.trim());
+    titles.join(" > ")
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, level: usize, page: Option<usize>, below_top: bool) -> PdfOutlineItem {
+        PdfOutlineItem {
+            title: title.to_owned(),
+            level,
+            page,
+            below_top,
+        }
+    }
+
+    fn test_outline() -> Vec<PdfOutlineItem> {
+        vec![
+            item("Introduction", 1, Some(1), false),
+            item("Background", 2, Some(2), false),
+            item("Methods", 1, Some(3), true),
+            item("Setup", 2, Some(3), true),
+            item("Results", 1, Some(5), false),
+            item("Summary", 2, Some(5), true),
+            item("Appendix", 1, None, false),
+            item("Summary", 1, Some(9), false),
+        ]
+    }
+
+    #[test]
+    fn sections_end_before_the_next_title() {
+        let items = test_outline();
+        // “Methods” starts below the top of page 3, so “Introduction” ends on that page
+        assert_eq!(section_page_range(&items, 0, 8), Some(0..3));
+        assert_eq!(section_page_range(&items, 1, 8), Some(1..3));
+        assert_eq!(section_page_range(&items, 2, 8), Some(2..4));
+        assert_eq!(section_page_range(&items, 3, 8), Some(2..4));
+        // Items without a page are skipped, and the end is clamped to the last page
+        assert_eq!(section_page_range(&items, 4, 8), Some(4..8));
+        assert_eq!(section_page_range(&items, 5, 8), Some(4..8));
+        assert_eq!(section_page_range(&items, 6, 8), None);
+        assert_eq!(section_page_range(&items, 7, 8), None);
+    }
+
+    #[test]
+    fn sections_have_at_least_one_page() {
+        let items = [
+            item("Title", 1, Some(1), false),
+            item("Contents", 1, Some(1), false),
+            item("Chapter", 1, Some(2), false),
+        ];
+        assert_eq!(section_page_range(&items, 0, 3), Some(0..1));
+        assert_eq!(section_page_range(&items, 1, 3), Some(0..1));
+        assert_eq!(section_page_range(&items, 2, 3), Some(1..3));
+    }
+
+    #[test]
+    fn finds_sections_by_title_or_path() {
+        let items = test_outline();
+        assert_eq!(find_section(&items, "methods").unwrap(), 2);
+        assert_eq!(
+            find_section(&items, " introduction >  BACKGROUND ").unwrap(),
+            1
+        );
+        // The path tells apart sections with the same title
+        assert_eq!(find_section(&items, "Results > Summary").unwrap(), 5);
+        assert_eq!(find_section(&items, "Setup").unwrap(), 3);
+        assert!(find_section(&items, "Conclusion").is_err());
+        assert!(find_section(&items, " > ").is_err());
+        // Two sections with this title start on different pages
+        assert!(find_section(&items, "Summary").is_err());
+    }
+}
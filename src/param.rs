@@ -11,9 +11,43 @@ pub struct GetPdfNumPagesParams {
         example = "./example-document.pdf"
     )]
     pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_get_pdf_metadata")]
+pub struct GetPdfMetadataParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
+    #[serde(default)]
+    #[schemars(description = "Also return the raw XMP packet, which may be long")]
+    pub include_xmp_packet: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_get_pdf_outline")]
+pub struct GetPdfOutlineParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
+    #[schemars(description = "Omit items nested deeper than this level", example = None::<usize>, range(min = 1))]
+    pub max_level: Option<usize>,
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 #[schemars(title = "pdflens_read_pdf_as_images")]
@@ -24,18 +58,19 @@ pub struct ReadPdfAsImagesParams {
         example = "./example-document.pdf"
     )]
     pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
     #[serde(default = "const_usize::<1>")]
     #[schemars(range(min = 1))]
     pub from_page: usize,
     #[schemars(range(min = 1))]
     pub to_page: Option<usize>,
-    #[serde(default = "const_u16::<1024>")]
     #[schemars(
-        description = "Number of pixels on the longer side of each output image",
+        description = "Number of pixels on the longer side of each output image. Leave empty to use the server’s default.",
         example = 1024,
         range(min = 1)
     )]
-    pub image_dimension: u16,
+    pub image_dimension: Option<u16>,
 }
 
 #[derive(Clone, Serialize, Deserialize, JsonSchema)]
@@ -48,11 +83,156 @@ pub struct ReadPdfAsTextParams {
         example = "./example-document.pdf"
     )]
     pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
+    #[serde(default = "const_usize::<1>")]
+    #[schemars(range(min = 1))]
+    pub from_page: usize,
+    #[schemars(description = "Out-of-range is fine", example = None::<usize>, range(min = 1))]
+    pub to_page: Option<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_read_pdf_section")]
+pub struct ReadPdfSectionParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
+    #[schemars(
+        description = "A title in the outline. Use “>” to separate the titles of parent and child sections if the title alone is ambiguous.",
+        example = "3 Design",
+        example = "3 Design > 3.2 Storage"
+    )]
+    pub section: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_search_pdf")]
+pub struct SearchPdfParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
+    pub query: String,
+    #[serde(default)]
+    #[schemars(description = "Treat `query` as a regular expression in Rust syntax")]
+    pub regex: bool,
+    #[serde(default = "const_bool::<true>")]
+    pub ignore_case: bool,
+    #[serde(default = "const_bool::<true>")]
+    #[schemars(
+        description = "Match regardless of spaces and line breaks. Only applies if `regex` is false."
+    )]
+    pub ignore_whitespace: bool,
     #[serde(default = "const_usize::<1>")]
     #[schemars(range(min = 1))]
     pub from_page: usize,
     #[schemars(description = "Out-of-range is fine", example = None::<usize>, range(min = 1))]
     pub to_page: Option<usize>,
+    #[serde(default = "const_usize::<80>")]
+    #[schemars(description = "Number of characters around each match to show")]
+    pub context_chars: usize,
+    #[serde(default = "const_usize::<3>")]
+    pub max_snippets_per_page: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_search_workspace_pdfs")]
+pub struct SearchWorkspacePdfsParams {
+    pub query: String,
+    #[serde(default)]
+    #[schemars(description = "Treat `query` as a regular expression in Rust syntax")]
+    pub regex: bool,
+    #[serde(default = "const_bool::<true>")]
+    pub ignore_case: bool,
+    #[serde(default = "const_bool::<true>")]
+    #[schemars(
+        description = "Match regardless of spaces and line breaks. Only applies if `regex` is false."
+    )]
+    pub ignore_whitespace: bool,
+    #[serde(default)]
+    #[schemars(
+        description = "Only search PDFs matching any of these globs, relative to the workspace directories",
+        example = ["docs/**"]
+    )]
+    pub include: Vec<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Skip PDFs matching any of these globs, relative to the workspace directories",
+        example = ["**/archive/**"]
+    )]
+    pub exclude: Vec<String>,
+    #[serde(default = "const_usize::<20>")]
+    pub max_files: usize,
+    #[serde(default = "const_usize::<10>")]
+    pub max_pages_per_file: usize,
+    #[serde(default = "const_usize::<80>")]
+    #[schemars(description = "Number of characters around each match to show")]
+    pub context_chars: usize,
+    #[serde(default = "const_usize::<2>")]
+    pub max_snippets_per_page: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_list_pdfs")]
+pub struct ListPdfsParams {
+    #[serde(default)]
+    #[schemars(
+        description = "Only list PDFs matching any of these globs, relative to the workspace directories",
+        example = ["docs/**"]
+    )]
+    pub include: Vec<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Skip PDFs matching any of these globs, relative to the workspace directories",
+        example = ["**/archive/**"]
+    )]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Maximum directory depth to look into. 1 only lists PDFs directly inside the workspace directories. Unlimited if omitted."
+    )]
+    pub max_depth: Option<usize>,
+    #[serde(default)]
+    #[schemars(description = "Open each PDF to count its pages, which is slower")]
+    pub include_num_pages: bool,
+    #[serde(default = "const_usize::<200>")]
+    pub max_files: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_search_pdf_index")]
+pub struct SearchPdfIndexParams {
+    #[schemars(description = "Words to search for, case-insensitive")]
+    pub query: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Only return PDFs matching any of these globs, relative to the workspace directories",
+        example = ["docs/**"]
+    )]
+    pub include: Vec<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Skip PDFs matching any of these globs, relative to the workspace directories",
+        example = ["**/archive/**"]
+    )]
+    pub exclude: Vec<String>,
+    #[serde(default = "const_usize::<20>")]
+    pub max_results: usize,
 }
 
 #[derive(Clone, Serialize, Deserialize, JsonSchema)]
@@ -65,16 +245,73 @@ pub struct ReadPdfPageAsImageParams {
         example = "./example-document.pdf"
     )]
     pub path: String,
+    #[schemars(description = "Only needed if the PDF is encrypted")]
+    pub password: Option<String>,
     #[serde(default = "const_usize::<1>")]
     #[schemars(range(min = 1))]
     pub page: usize,
-    #[serde(default = "const_u16::<1024>")]
     #[schemars(
-        description = "Number of pixels on the longer side of each output image",
+        description = "Number of pixels on the longer side of each output image. Leave empty to use the server’s default.",
         example = 1024,
         range(min = 1)
     )]
-    pub image_dimension: u16,
+    pub image_dimension: Option<u16>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_summarize_pdf")]
+pub struct SummarizePdfPromptParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path: String,
+    // Prompt arguments are always strings
+    #[schemars(description = "First page number to read. Defaults to the first page.")]
+    pub from_page: Option<String>,
+    #[schemars(
+        description = "Last page number to read. Defaults to the last page, but at most 50 pages are read."
+    )]
+    pub to_page: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_extract_action_items")]
+pub struct ExtractActionItemsPromptParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path: String,
+    // Prompt arguments are always strings
+    #[schemars(description = "First page number to read. Defaults to the first page.")]
+    pub from_page: Option<String>,
+    #[schemars(
+        description = "Last page number to read. Defaults to the last page, but at most 50 pages are read."
+    )]
+    pub to_page: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_compare_pdfs")]
+pub struct ComparePdfsPromptParams {
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path1: String,
+    #[schemars(
+        description = "Absolute paths should start with `file:///`. Relative paths are relative to any of the user’s current workspace directories.",
+        example = "file:///C:/Users/Admin/Documents/example-project/example-document.pdf",
+        example = "./example-document.pdf"
+    )]
+    pub path2: String,
 }
 
 #[derive(Clone, Serialize, Deserialize, JsonSchema)]
@@ -85,8 +322,193 @@ pub struct GetPdfNumPagesResult {
     pub num_pages: usize,
 }
 
-const fn const_u16<const N: u16>() -> u16 {
-    N
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_get_pdf_metadata")]
+pub struct GetPdfMetadataResult {
+    pub num_pages: usize,
+    #[schemars(example = "1.7")]
+    pub pdf_version: Option<String>,
+    #[schemars(
+        description = "Whether the PDF is encrypted, even if it opens without a password, e.g. when only editing and printing are restricted"
+    )]
+    pub encrypted: bool,
+    #[schemars(description = "Whether a password is needed to open the PDF")]
+    pub password_required: bool,
+    #[schemars(description = "Whether the PDF has logical structure for accessibility")]
+    pub tagged: bool,
+    #[schemars(description = "Whether the PDF is optimized for fast web view")]
+    pub linearized: bool,
+    #[schemars(description = "Document information dictionary")]
+    pub info: PdfInfo,
+    #[schemars(description = "XMP metadata")]
+    pub xmp: Option<PdfXmp>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub subject: Option<String>,
+    pub keywords: Option<String>,
+    #[schemars(description = "The application that created the original document")]
+    pub creator: Option<String>,
+    #[schemars(description = "The application that converted the document to PDF")]
+    pub producer: Option<String>,
+    #[schemars(example = "2024-01-31T12:00:00+08:00")]
+    pub creation_date: Option<String>,
+    #[schemars(example = "2024-01-31T12:00:00+08:00")]
+    pub modification_date: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct PdfXmp {
+    pub title: Option<String>,
+    pub creators: Vec<String>,
+    pub description: Option<String>,
+    pub subjects: Vec<String>,
+    pub keywords: Option<String>,
+    pub creator_tool: Option<String>,
+    pub producer: Option<String>,
+    pub create_date: Option<String>,
+    pub modify_date: Option<String>,
+    pub metadata_date: Option<String>,
+    #[schemars(description = "PDF/A part number, if the PDF claims PDF/A conformance")]
+    pub pdfa_part: Option<String>,
+    #[schemars(description = "PDF/A conformance level, if the PDF claims PDF/A conformance")]
+    pub pdfa_conformance: Option<String>,
+    pub packet: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_get_pdf_outline")]
+pub struct GetPdfOutlineResult {
+    #[schemars(description = "Empty if the PDF has no outline")]
+    pub items: Vec<PdfOutlineItem>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct PdfOutlineItem {
+    pub title: String,
+    #[schemars(description = "Top-level items are at level 1", range(min = 1))]
+    pub level: usize,
+    #[schemars(
+        description = "1-based page number, or null if the target is unknown or outside this PDF",
+        range(min = 1)
+    )]
+    pub page: Option<usize>,
+    // Whether the target is below the top of its page, so the previous section may end above it
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub(crate) below_top: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_search_pdf")]
+pub struct SearchPdfResult {
+    pub total_matches: usize,
+    #[schemars(description = "Pages with at least one match")]
+    pub pages: Vec<PdfSearchPageHit>,
+    #[schemars(description = "Pages whose text could not be extracted")]
+    pub failed_pages: Vec<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct PdfSearchPageHit {
+    pub page: usize,
+    pub match_count: usize,
+    pub snippets: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_search_workspace_pdfs")]
+pub struct SearchWorkspacePdfsResult {
+    pub searched_files: usize,
+    #[schemars(description = "Files with at least one match, the most matches first")]
+    pub files: Vec<WorkspacePdfHit>,
+    pub failed_files: Vec<WorkspacePdfFailure>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct WorkspacePdfHit {
+    pub uri: String,
+    pub match_count: usize,
+    pub pages: Vec<PdfSearchPageHit>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct WorkspacePdfFailure {
+    pub uri: String,
+    pub error: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_list_pdfs")]
+pub struct ListPdfsResult {
+    pub files: Vec<WorkspacePdfEntry>,
+    #[schemars(description = "Whether more PDFs were found than `maxFiles`")]
+    pub truncated: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct WorkspacePdfEntry {
+    #[schemars(description = "Pass this as `path` to the other tools")]
+    pub uri: String,
+    #[schemars(description = "File size in bytes")]
+    pub size: u64,
+    #[schemars(
+        description = "Absent if the PDF is encrypted, damaged, or `includeNumPages` is false"
+    )]
+    pub num_pages: Option<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_search_pdf_index")]
+pub struct SearchPdfIndexResult {
+    pub indexed_files: usize,
+    #[schemars(description = "Number of new or modified PDFs indexed during this search")]
+    pub updated_files: usize,
+    #[schemars(description = "The most relevant pages first")]
+    pub hits: Vec<PdfIndexHit>,
+    #[schemars(description = "Encrypted PDFs are never indexed. Use `search_pdf` to search them.")]
+    pub encrypted_files: Vec<String>,
+    #[schemars(description = "PDFs that failed to load. They are tried again on the next search.")]
+    pub failed_files: Vec<WorkspacePdfFailure>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct PdfIndexHit {
+    pub uri: String,
+    pub page: usize,
+    pub score: f64,
+    pub matched_terms: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+#[schemars(title = "pdflens_pdf_password")]
+pub struct PdfPasswordElicitation {
+    #[schemars(description = "The password will not be shown to the AI")]
+    pub password: String,
+}
+
+rmcp::elicit_safe!(PdfPasswordElicitation);
+
+const fn const_bool<const B: bool>() -> bool {
+    B
 }
 
 const fn const_usize<const N: usize>() -> usize {
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use globset::GlobSet;
+use indexmap::IndexSet;
+use rmcp::{Peer, RoleServer};
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientRootsMode {
+    /// Use the client’s roots together with the configured roots
+    #[default]
+    Augment,
+    /// Only use the client’s roots, unless the client provides none
+    Replace,
+    /// Only use the configured roots, e.g. when the client runs on another machine
+    Ignore,
+}
+
+/// Decides which files the MCP client may read, in addition to its own root directories.
+pub struct AccessPolicy {
+    /// Canonical paths
+    pub roots: IndexSet<PathBuf>,
+    pub client_roots: ClientRootsMode,
+    /// Whether symbolic links inside a root directory may point outside of it
+    pub allow_symlink_escape: bool,
+    deny: Option<GlobSet>,
+    file_policy: Arc<dyn FilePolicy>,
+}
+
+/// Lets a server that embeds pdflens decide the root directories of each session, which files
+/// may be read, and how they are read.
+///
+/// Every method defaults to the behavior of the standalone server.
+pub trait FilePolicy: Send + Sync + 'static {
+    /// Returns the root directories of a session, given the ones merged from the client and the
+    /// access policy. They should be canonical paths.
+    fn resolve_roots(
+        &self,
+        roots: IndexSet<PathBuf>,
+        _peer: &Peer<RoleServer>,
+    ) -> IndexSet<PathBuf> {
+        roots
+    }
+
+    /// Whether a file or directory inside the root directories may be read. Directories that
+    /// are not allowed are not searched for PDFs.
+    fn is_allowed(&self, _path: &Path) -> bool {
+        true
+    }
+
+    /// Reads a PDF, given its canonical path. Called on a blocking thread.
+    ///
+    /// Only the contents are read through the policy. The path is still resolved, and its
+    /// modification time and size are still checked for caching, on the local file system. So
+    /// the file must exist locally, e.g. on a mounted file system.
+    fn read_file(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
+
+/// Reads files from the local file system, and allows every file inside the root directories.
+pub struct DefaultFilePolicy;
+
+impl FilePolicy for DefaultFilePolicy {}
+
+impl AccessPolicy {
+    /// Allows reading PDFs inside `roots`, which should be canonical paths, in addition to the
+    /// root directories of the client.
+    pub fn new(roots: IndexSet<PathBuf>) -> Self {
+        Self {
+            roots,
+            client_roots: ClientRootsMode::default(),
+            allow_symlink_escape: false,
+            deny: None,
+            file_policy: Arc::new(DefaultFilePolicy),
+        }
+    }
+
+    /// Never reads paths matching `deny`, or inside directories matching it. The globs should be
+    /// case-insensitive on case-insensitive file systems.
+    pub fn with_deny(mut self, deny: GlobSet) -> Self {
+        self.deny = Some(deny);
+        self
+    }
+
+    pub fn with_file_policy(mut self, file_policy: Arc<dyn FilePolicy>) -> Self {
+        self.file_policy = file_policy;
+        self
+    }
+
+    pub fn file_policy(&self) -> &dyn FilePolicy {
+        &*self.file_policy
+    }
+
+    /// Whether a path, or any directory containing it, matches a deny glob, or the file policy
+    /// does not allow it.
+    ///
+    /// The globs are matched against the path relative to `root`, or against the whole path if
+    /// it is outside, e.g. after following a symbolic link.
+    pub fn is_denied(&self, path: &Path, root: &Path) -> bool {
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        self.deny.as_ref().is_some_and(|deny| {
+            relative_path
+                .ancestors()
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .any(|ancestor| deny.is_match(ancestor))
+        }) || !self.file_policy.is_allowed(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use globset::{GlobBuilder, GlobSetBuilder};
+
+    use super::*;
+
+    fn deny(patterns: &[&str]) -> AccessPolicy {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(
+                GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .unwrap(),
+            );
+        }
+        AccessPolicy::new(IndexSet::new()).with_deny(builder.build().unwrap())
+    }
+
+    #[test]
+    fn deny_globs_match_relative_to_root() {
+        let policy = deny(&["private", "**/*.secret.pdf"]);
+        let root = Path::new("/home/user/docs");
+        assert!(policy.is_denied(Path::new("/home/user/docs/private"), root));
+        assert!(policy.is_denied(Path::new("/home/user/docs/private/a.pdf"), root));
+        assert!(policy.is_denied(Path::new("/home/user/docs/a.secret.pdf"), root));
+        assert!(policy.is_denied(Path::new("/home/user/docs/sub/a.secret.pdf"), root));
+        assert!(!policy.is_denied(Path::new("/home/user/docs/a.pdf"), root));
+        // `private` without a separator only matches at the top of the root
+        assert!(!policy.is_denied(Path::new("/home/user/docs/sub/private/a.pdf"), root));
+    }
+
+    #[test]
+    fn deny_globs_ignore_the_root_itself() {
+        let policy = deny(&["**/private/**"]);
+        let root = Path::new("/home/private/docs");
+        assert!(!policy.is_denied(Path::new("/home/private/docs/a.pdf"), root));
+        assert!(policy.is_denied(Path::new("/home/private/docs/private/a.pdf"), root));
+    }
+
+    #[test]
+    fn deny_globs_match_whole_path_outside_root() {
+        let policy = deny(&["**/private/**"]);
+        let root = Path::new("/home/user/docs");
+        assert!(policy.is_denied(Path::new("/mnt/private/a.pdf"), root));
+        assert!(!policy.is_denied(Path::new("/mnt/public/a.pdf"), root));
+    }
+
+    #[test]
+    fn file_policy_can_deny() {
+        struct NoDrafts;
+        impl FilePolicy for NoDrafts {
+            fn is_allowed(&self, path: &Path) -> bool {
+                !path.ends_with("drafts")
+            }
+        }
+        let policy = AccessPolicy::new(IndexSet::new()).with_file_policy(Arc::new(NoDrafts));
+        let root = Path::new("/home/user/docs");
+        assert!(policy.is_denied(Path::new("/home/user/docs/drafts"), root));
+        assert!(!policy.is_denied(Path::new("/home/user/docs/final"), root));
+    }
+}
@@ -0,0 +1,187 @@
+use eyre::{Result, bail, eyre};
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+pub const PDFLENS_SCHEME: &str = "pdflens";
+pub const PAGE_TEXT_TEMPLATE: &str = "pdflens:///{+path}/pages/{page}/text";
+pub const PAGE_IMAGE_TEMPLATE: &str = "pdflens:///{+path}/pages/{page}/image";
+pub const PAGE_FRAGMENT_TEMPLATE: &str = "pdflens:///{+path}#page={page}";
+
+/// A parsed resource URI, which points to a whole PDF or to one of its pages.
+///
+/// Accepted forms:
+/// * `file:///docs/spec.pdf`
+/// * `pdflens:///docs/spec.pdf`, where the path may be relative to the workspace directories
+/// * Either of the above followed by `#page=12`
+/// * `pdflens:///docs/spec.pdf/pages/12/text` or `…/pages/12/image`
+///
+/// The query string may contain `format=text` or `format=png`, and `imageDimension=1024`.
+pub struct PdfResourceUri {
+    /// Either a `file:` URI or a path, in the form that the `path` parameter of the tools accepts
+    pub path: String,
+    /// 1-based page number
+    pub page: Option<usize>,
+    pub render_images: bool,
+    /// Falls back to the configured default if not given
+    pub image_dimension: Option<u16>,
+}
+
+impl PdfResourceUri {
+    /// Returns `None` if the URI is not handled by this server.
+    pub fn parse(uri: &str) -> Result<Option<Self>> {
+        let Ok(mut url) = Url::parse(uri) else {
+            return Ok(None);
+        };
+        let is_pdflens = url.scheme().eq_ignore_ascii_case(PDFLENS_SCHEME);
+        if !is_pdflens && !url.scheme().eq_ignore_ascii_case("file") {
+            return Ok(None);
+        }
+
+        let mut render_images = false;
+        let mut image_dimension = None;
+        for (key, value) in url.query_pairs() {
+            match (&*key, &*value) {
+                ("format", "text") => render_images = false,
+                ("format", "png") => render_images = true,
+                ("imageDimension", value) => {
+                    image_dimension = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|&x| x != 0)
+                            .ok_or_else(|| eyre!("Invalid image dimension: {value:?}"))?,
+                    );
+                }
+                _ => bail!("Unknown query parameter in resource URI: {key}={value}"),
+            }
+        }
+
+        let mut page = match url.fragment() {
+            None | Some("") => None,
+            Some(fragment) => Some(
+                fragment
+                    .strip_prefix("page=")
+                    .and_then(|x| x.parse().ok())
+                    .ok_or_else(|| eyre!("Invalid fragment in resource URI: #{fragment}"))?,
+            ),
+        };
+        url.set_query(None);
+        url.set_fragment(None);
+
+        if !is_pdflens {
+            return Ok(Some(Self {
+                path: url.into(),
+                page,
+                render_images,
+                image_dimension,
+            }));
+        }
+
+        // Only strip the first slash, so `pdflens:////abs/file.pdf` refers to an absolute path
+        let mut path = url.path().strip_prefix('/').unwrap_or(url.path());
+        let mut segments = path.rsplitn(4, '/');
+        if let (Some(kind), Some(page_num), Some("pages"), Some(pdf_path)) = (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) && let Ok(page_num) = page_num.parse()
+        {
+            match kind {
+                "text" => render_images = false,
+                "image" => render_images = true,
+                _ => bail!(
+                    "Unknown page resource type: {kind:?}\nIt should be either “text” or “image”."
+                ),
+            }
+            page = Some(page_num);
+            path = pdf_path;
+        }
+        if path.is_empty() {
+            bail!("The resource URI does not contain a path: {uri:?}");
+        }
+
+        Ok(Some(Self {
+            path: percent_decode_str(path).decode_utf8()?.into_owned(),
+            page,
+            render_images,
+            image_dimension,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(uri: &str) -> PdfResourceUri {
+        PdfResourceUri::parse(uri).unwrap().unwrap()
+    }
+
+    #[test]
+    fn ignores_other_schemes() {
+        assert!(
+            PdfResourceUri::parse("https://example.com/a.pdf")
+                .unwrap()
+                .is_none()
+        );
+        assert!(PdfResourceUri::parse("docs/a.pdf").unwrap().is_none());
+    }
+
+    #[test]
+    fn parses_file_uris() {
+        let uri = parse("file:///docs/spec.pdf");
+        assert_eq!(uri.path, "file:///docs/spec.pdf");
+        assert_eq!(uri.page, None);
+        assert!(!uri.render_images);
+
+        let uri = parse("file:///docs/spec.pdf?format=png#page=3");
+        assert_eq!(uri.path, "file:///docs/spec.pdf");
+        assert_eq!(uri.page, Some(3));
+        assert!(uri.render_images);
+    }
+
+    #[test]
+    fn parses_pdflens_paths() {
+        let uri = parse("pdflens:///docs/my%20spec.pdf");
+        assert_eq!(uri.path, "docs/my spec.pdf");
+        assert_eq!(uri.page, None);
+
+        let uri = parse("pdflens:////abs/spec.pdf#page=2");
+        assert_eq!(uri.path, "/abs/spec.pdf");
+        assert_eq!(uri.page, Some(2));
+    }
+
+    #[test]
+    fn parses_page_resources() {
+        let uri = parse("pdflens:///docs/spec.pdf/pages/12/text");
+        assert_eq!(uri.path, "docs/spec.pdf");
+        assert_eq!(uri.page, Some(12));
+        assert!(!uri.render_images);
+
+        let uri = parse("pdflens:///docs/spec.pdf/pages/12/image?imageDimension=512");
+        assert_eq!(uri.path, "docs/spec.pdf");
+        assert_eq!(uri.page, Some(12));
+        assert!(uri.render_images);
+        assert_eq!(uri.image_dimension, Some(512));
+
+        // Not a page number, so it is part of the path
+        let uri = parse("pdflens:///docs/pages/intro/text");
+        assert_eq!(uri.path, "docs/pages/intro/text");
+        assert_eq!(uri.page, None);
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        for uri in [
+            "pdflens:///",
+            "pdflens:///docs/spec.pdf/pages/12/json",
+            "pdflens:///docs/spec.pdf?format=xml",
+            "pdflens:///docs/spec.pdf?imageDimension=0",
+            "pdflens:///docs/spec.pdf#chapter=2",
+            "file:///docs/spec.pdf#page=two",
+        ] {
+            assert!(PdfResourceUri::parse(uri).is_err(), "{uri}");
+        }
+    }
+}
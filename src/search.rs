@@ -0,0 +1,163 @@
+use std::ops::Range;
+
+use eyre::{Result, bail, eyre};
+use hayro::hayro_syntax::Pdf;
+use pdf_extract::{PlainTextOutput, output_doc_page};
+use regex::{Regex, RegexBuilder};
+
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Clone)]
+pub struct SearchQuery {
+    regex: Regex,
+}
+
+pub struct PageMatches {
+    pub match_count: usize,
+    pub snippets: Vec<String>,
+}
+
+impl SearchQuery {
+    /// `ignore_whitespace` only applies to literal queries. It lets the query match across line
+    /// breaks and the stray spaces that text extraction tends to insert.
+    pub fn new(
+        query: &str,
+        is_regex: bool,
+        ignore_case: bool,
+        ignore_whitespace: bool,
+    ) -> Result<Self> {
+        let pattern = if is_regex {
+            query.to_owned()
+        } else if ignore_whitespace {
+            query
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| regex::escape(c.encode_utf8(&mut [0; 4])))
+                .collect::<Vec<_>>()
+                .join(r"\s*")
+        } else {
+            regex::escape(query)
+        };
+        if pattern.is_empty() {
+            bail!("The search query is empty.");
+        }
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|err| eyre!("Invalid search query: {err}"))?;
+        Ok(Self { regex })
+    }
+
+    pub fn search(&self, text: &str, context_chars: usize, max_snippets: usize) -> PageMatches {
+        let mut match_count = 0;
+        let mut snippets = Vec::new();
+        // Empty matches are useless and would make every position count
+        for m in self.regex.find_iter(text).filter(|m| !m.is_empty()) {
+            match_count += 1;
+            if snippets.len() < max_snippets {
+                snippets.push(snippet(text, m.range(), context_chars));
+            }
+        }
+        PageMatches {
+            match_count,
+            snippets,
+        }
+    }
+}
+
+/// Extracts the text of one page, given its 0-based index.
+pub fn extract_page_text(pdf: &Pdf, page_idx: usize) -> Result<String> {
+    let mut text = String::new();
+    let mut device = PlainTextOutput::new(&mut text);
+    output_doc_page(pdf, &mut device, u32::try_from(page_idx)? + 1)?;
+    Ok(text)
+}
+
+fn snippet(text: &str, range: Range<usize>, context_chars: usize) -> String {
+    let start = text[..range.start]
+        .char_indices()
+        .rev()
+        .take(context_chars)
+        .last()
+        .map_or(range.start, |(idx, _)| idx);
+    let end = text[range.end..]
+        .char_indices()
+        .nth(context_chars)
+        .map_or(text.len(), |(idx, _)| range.end + idx);
+
+    let mut builder = String::new();
+    if start != 0 {
+        builder.push('…');
+    }
+    for (i, word) in text[start..end].split_whitespace().enumerate() {
+        if i != 0 {
+            builder.push(' ');
+        }
+        builder.push_str(word);
+    }
+    if end != text.len() {
+        builder.push('…');
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(query: &SearchQuery, text: &str) -> usize {
+        query.search(text, 0, 0).match_count
+    }
+
+    #[test]
+    fn literal_queries_are_escaped() {
+        let query = SearchQuery::new("a.b", false, false, false).unwrap();
+        assert_eq!(count(&query, "axb a.b"), 1);
+        let query = SearchQuery::new("A.B", false, true, false).unwrap();
+        assert_eq!(count(&query, "axb a.b"), 1);
+    }
+
+    #[test]
+    fn literal_queries_can_ignore_whitespace() {
+        let query = SearchQuery::new("hello world", false, false, true).unwrap();
+        assert_eq!(count(&query, "hel\nlo   world, helloworld, hello_world"), 2);
+        let query = SearchQuery::new("hello world", false, false, false).unwrap();
+        assert_eq!(count(&query, "hello\nworld"), 0);
+    }
+
+    #[test]
+    fn regex_queries() {
+        let query = SearchQuery::new(r"\d+", true, false, false).unwrap();
+        assert_eq!(count(&query, "1 22 333"), 3);
+        // Empty matches are not counted
+        let query = SearchQuery::new("x*", true, false, false).unwrap();
+        assert_eq!(count(&query, "abc"), 0);
+    }
+
+    #[test]
+    fn rejects_empty_or_invalid_queries() {
+        assert!(SearchQuery::new("", false, false, false).is_err());
+        assert!(SearchQuery::new(" \n", false, false, true).is_err());
+        assert!(SearchQuery::new("(", true, false, false).is_err());
+    }
+
+    #[test]
+    fn snippets_have_context() {
+        let query = SearchQuery::new("brown", false, false, false).unwrap();
+        let matches = query.search("the quick brown\nfox", 4, 10);
+        assert_eq!(matches.snippets, ["…ick brown fox"]);
+
+        let query = SearchQuery::new("résumé", false, false, false).unwrap();
+        let matches = query.search("café résumé", 2, 10);
+        assert_eq!(matches.snippets, ["…é résumé"]);
+    }
+
+    #[test]
+    fn snippets_are_limited() {
+        let query = SearchQuery::new("a", false, false, false).unwrap();
+        let matches = query.search("a a a", 0, 2);
+        assert_eq!(matches.match_count, 3);
+        assert_eq!(matches.snippets, ["a…", "…a…"]);
+    }
+}
@@ -1,68 +1,292 @@
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 
 use base64::prelude::*;
 use eyre::{Result, bail, eyre};
 use hayro::RenderSettings;
 use hayro::hayro_interpret::InterpreterSettings;
-use hayro::hayro_syntax::Pdf;
+use hayro::hayro_syntax::page::Page;
+use hayro::hayro_syntax::{DecryptionError, LoadPdfError, Pdf};
 use hayro::vello_cpu::color::palette::css::WHITE;
 use indexmap::IndexSet;
-use pdf_extract::{PlainTextOutput, output_doc_page};
-use rmcp::handler::server::tool::{IntoCallToolResult, ToolRouter, schema_for_type};
+use rmcp::handler::server::prompt::PromptContext;
+use rmcp::handler::server::router::prompt::PromptRouter;
+use rmcp::handler::server::tool::{
+    IntoCallToolResult, ToolCallContext, ToolRouter, schema_for_type,
+};
 use rmcp::handler::server::wrapper::Parameters;
 use rmcp::model::{
-    CallToolResult, Content, Implementation, ProgressNotificationParam, Role, ServerCapabilities,
-    ServerInfo,
+    AnnotateAble, CallToolRequestParams, CallToolResult, CompleteRequestParams, CompleteResult,
+    CompletionInfo, Content, GetPromptRequestParams, GetPromptResult, Implementation,
+    ListPromptsResult, ListResourceTemplatesResult, ListResourcesResult, ListToolsResult,
+    PaginatedRequestParams, ProgressNotificationParam, PromptMessage, PromptMessageRole,
+    RawResource, RawResourceTemplate, ReadResourceRequestParams, ReadResourceResult,
+    ResourceContents, Role, ServerCapabilities, ServerInfo, SetLevelRequestParams,
+    SubscribeRequestParams, UnsubscribeRequestParams,
 };
-use rmcp::service::RequestContext;
+use rmcp::service::{ElicitationError, ElicitationMode, NotificationContext, RequestContext};
 use rmcp::{Json, Peer, RoleServer, ServerHandler};
 use tokio::task::spawn_blocking;
-use tracing::instrument;
+use tokio_util::sync::CancellationToken;
+use tracing::{Span, instrument};
 use url::Url;
 
+use crate::cache::{LoadedPdf, PdfCache};
+use crate::index::{IndexStats, IndexedFile, PdfIndexStore, tokenize};
+use crate::logging::McpLogSink;
+use crate::metadata::read_metadata;
+use crate::outline::{find_section, read_outline, section_page_range};
 use crate::param::{
-    GetPdfNumPagesParams, GetPdfNumPagesResult, ReadPdfAsImagesParams, ReadPdfAsTextParams,
-    ReadPdfPageAsImageParams,
+    ComparePdfsPromptParams, ExtractActionItemsPromptParams, GetPdfMetadataParams,
+    GetPdfMetadataResult, GetPdfNumPagesParams, GetPdfNumPagesResult, GetPdfOutlineParams,
+    GetPdfOutlineResult, ListPdfsParams, ListPdfsResult, PdfIndexHit, PdfPasswordElicitation,
+    PdfSearchPageHit, ReadPdfAsImagesParams, ReadPdfAsTextParams, ReadPdfPageAsImageParams,
+    ReadPdfSectionParams, SearchPdfIndexParams, SearchPdfIndexResult, SearchPdfParams,
+    SearchPdfResult, SearchWorkspacePdfsParams, SearchWorkspacePdfsResult,
+    SummarizePdfPromptParams, WorkspacePdfEntry, WorkspacePdfFailure, WorkspacePdfHit,
+};
+use crate::policy::{AccessPolicy, ClientRootsMode};
+use crate::resource::{
+    PAGE_FRAGMENT_TEMPLATE, PAGE_IMAGE_TEMPLATE, PAGE_TEXT_TEMPLATE, PdfResourceUri,
 };
+use crate::search::SearchQuery;
+use crate::watch::PdfWatcher;
+use crate::worker::PdfWorkers;
+use crate::workspace::{
+    PdfFilter, PdfListCache, WorkspacePdf, complete_pdf_path, find_pdfs, normalize_lexically,
+    path_to_uri, resolve_in_root, suggest_pdf_paths,
+};
+
+const MAX_PASSWORD_ELICITATIONS: usize = 3;
+const DEFAULT_IMAGE_DIMENSION: u16 = 1024;
+// Most MCP clients cannot handle several images in one tool result
+const DEFAULT_DISABLED_TOOLS: &[&str] = &["read_pdf_as_images"];
+// The MCP specification allows at most 100 completion values per response
+const MAX_COMPLETION_VALUES: usize = 100;
+// Rendering every page of a long PDF into one response would be far too large
+const MAX_RESOURCE_IMAGE_PAGES: usize = 20;
+// Prompts cannot be paged through like tool results, so long PDFs are cut off
+const MAX_PROMPT_PAGES: usize = 50;
+
+#[derive(Debug)]
+pub enum PdfPasswordError {
+    Required,
+    Incorrect,
+}
+
+impl std::fmt::Display for PdfPasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Required => f.write_str(
+                "The PDF is encrypted and requires a password.\nThe user must enter it outside of the chat, through an MCP client that supports elicitation, or with `PDFLENS_PASSWORD` on the command line. Do not ask the user to type the password in the chat.",
+            ),
+            Self::Incorrect => f.write_str(
+                "The password for the encrypted PDF is incorrect.\nThe user must enter the correct password outside of the chat, through an MCP client that supports elicitation, or with `PDFLENS_PASSWORD` on the command line. Do not ask the user to type the password in the chat.",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PdfPasswordError {}
+
+/// A relative path that exists in more than one root directory.
+#[derive(Debug)]
+pub struct AmbiguousPathError {
+    pub path: PathBuf,
+    /// Real paths of the candidates
+    pub candidates: Vec<PathBuf>,
+}
+
+impl std::fmt::Display for AmbiguousPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Path is ambiguous: {:?}\nIt exists in more than one of the user’s current workspace directories. Please use one of the following absolute paths instead:",
+            self.path
+        )?;
+        for candidate in &self.candidates {
+            write!(f, "\n* {}", path_to_uri(candidate))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AmbiguousPathError {}
+
+/// Decides which tools are offered to the MCP client, and the defaults of their parameters.
+pub struct ToolConfig {
+    pub disabled_tools: HashSet<String>,
+    pub image_dimension: u16,
+    /// Watch the root directories as soon as the client lists the resources. Otherwise, they are
+    /// only watched after the client subscribes to a resource.
+    pub watch_roots: bool,
+}
+
+impl Default for ToolConfig {
+    fn default() -> Self {
+        Self {
+            disabled_tools: DEFAULT_DISABLED_TOOLS
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+            image_dimension: DEFAULT_IMAGE_DIMENSION,
+            watch_roots: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CachedRoots {
+    roots: Option<IndexSet<PathBuf>>,
+    // Incremented whenever the roots change, so an older request to the client is not cached
+    generation: u64,
+}
 
 pub struct PdflensService {
     tool_router: ToolRouter<Self>,
+    prompt_router: PromptRouter<Self>,
+    pdf_cache: Arc<PdfCache>,
+    pdf_index: Arc<PdfIndexStore>,
+    access_policy: Arc<AccessPolicy>,
+    tool_config: Arc<ToolConfig>,
+    // Passwords that successfully opened a PDF in this session, so the user is only asked once
+    pdf_passwords: Mutex<HashMap<PathBuf, String>>,
+    // Cleared when the client sends `notifications/roots/list_changed`
+    roots: Mutex<CachedRoots>,
+    // Started on the first `resources/subscribe` request, or `resources/list` if `watch_roots`
+    pdf_watcher: Mutex<Option<Arc<PdfWatcher>>>,
+    // For completions, which are requested on every keystroke
+    pdf_list_cache: Arc<PdfListCache>,
+    log_sink: Arc<McpLogSink>,
+    pdf_workers: Arc<PdfWorkers>,
 }
 
 impl PdflensService {
-    pub fn new() -> Self {
+    pub fn new(
+        pdf_cache: Arc<PdfCache>,
+        pdf_index: Arc<PdfIndexStore>,
+        access_policy: Arc<AccessPolicy>,
+        tool_config: Arc<ToolConfig>,
+    ) -> Self {
+        let mut tool_router = Self::all_tools_router();
+        for name in &tool_config.disabled_tools {
+            tool_router.remove_route(name.as_str());
+        }
         Self {
-            tool_router: Self::tool_router(),
+            tool_router,
+            prompt_router: Self::prompt_router(),
+            pdf_cache,
+            pdf_index,
+            access_policy,
+            tool_config,
+            pdf_passwords: Mutex::default(),
+            roots: Mutex::default(),
+            pdf_watcher: Mutex::default(),
+            pdf_list_cache: Arc::default(),
+            log_sink: McpLogSink::new(),
+            pdf_workers: Arc::default(),
+        }
+    }
+
+    /// Extracts text, and renders pages if configured, in worker processes. Otherwise, a crash
+    /// in the PDF reader ends the whole server.
+    pub fn with_pdf_workers(mut self, pdf_workers: Arc<PdfWorkers>) -> Self {
+        self.pdf_workers = pdf_workers;
+        self
+    }
+
+    /// Names of all tools that the server implements, whether they are enabled or not.
+    pub fn tool_names() -> Vec<String> {
+        Self::all_tools_router()
+            .list_all()
+            .into_iter()
+            .map(|tool| tool.name.into_owned())
+            .collect()
+    }
+
+    /// The tools offered to the client, without the disabled ones.
+    pub fn tool_router(&self) -> &ToolRouter<Self> {
+        &self.tool_router
+    }
+
+    /// Returns the root directories of this session. The client is only asked on the first call,
+    /// and again after it announces that its roots changed.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_roots(&self, peer: &Peer<RoleServer>) -> IndexSet<PathBuf> {
+        // The lock is not held while waiting for the client, so a slow client doesn't stall
+        // other requests. Concurrent callers may each ask the client, which is harmless.
+        let generation = {
+            let roots = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(roots) = &roots.roots {
+                return roots.clone();
+            }
+            roots.generation
+        };
+        let (new_roots, cacheable) = self.fetch_roots(peer).await;
+        let new_roots = self
+            .access_policy
+            .file_policy()
+            .resolve_roots(new_roots, peer);
+        {
+            let mut roots = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+            // Roots that changed during the request are fetched again on the next call
+            if roots.generation != generation {
+                return new_roots;
+            }
+            if cacheable {
+                roots.roots = Some(new_roots.clone());
+            }
+        }
+
+        // Follow the new roots if the client asked to be notified about resource changes
+        let pdf_watcher = self
+            .pdf_watcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if let Some(pdf_watcher) = pdf_watcher
+            && pdf_watcher.is_watching_roots()
+        {
+            pdf_watcher.watch_roots(&new_roots);
         }
+        new_roots
     }
 
+    /// Asks the client for its root directories. Also returns whether the result may be cached.
     #[tracing::instrument(skip_all)]
-    pub async fn get_roots(peer: &Peer<RoleServer>) -> IndexSet<PathBuf> {
+    async fn fetch_roots(&self, peer: &Peer<RoleServer>) -> (IndexSet<PathBuf>, bool) {
+        if self.access_policy.client_roots == ClientRootsMode::Ignore {
+            let roots = self.get_roots_fallback().await;
+            tracing::info!("Ignoring MCP client root paths, using default root paths: {roots:?}");
+            return (roots, true);
+        }
         if !peer
             .peer_info()
             .map(|peer_info| peer_info.capabilities.roots.is_some())
             .unwrap_or_default()
         {
-            let roots = Self::get_roots_fallback().await;
+            let roots = self.get_roots_fallback().await;
             tracing::warn!(
-                "MCP client does not support root path capability, falling back to current working directory: {roots:?}"
+                "MCP client does not support root path capability, falling back to default root paths: {roots:?}"
             );
-            return roots;
+            return (roots, true);
         }
 
         let uris = match peer.list_roots().await {
             Ok(roots) => roots.roots,
             Err(err) => {
-                let roots = Self::get_roots_fallback().await;
+                let roots = self.get_roots_fallback().await;
+                // Ask again next time, as the error may be temporary
                 tracing::error!(
-                    "Failed to request MCP root paths: {err}. Falling back to current working directory: {roots:?}"
+                    "Failed to request MCP root paths: {err}. Falling back to default root paths: {roots:?}"
                 );
-                return roots;
+                return (roots, false);
             }
         };
-        tracing::info!("Resolving MCP root paths: {uris:?}");
+        tracing::debug!("Resolving MCP root paths: {uris:?}");
 
         let mut roots = IndexSet::new();
         for root in uris {
@@ -76,7 +300,7 @@ impl PdflensService {
             };
             roots.insert(match tokio::fs::canonicalize(&path).await {
                 Ok(path) => {
-                    tracing::info!("Resolved MCP root path: {:?} → {path:?}", root.uri);
+                    tracing::debug!("Resolved MCP root path: {:?} → {path:?}", root.uri);
                     path
                 }
                 Err(err) => {
@@ -89,23 +313,31 @@ impl PdflensService {
             });
         }
         if roots.is_empty() {
-            let roots = Self::get_roots_fallback().await;
+            let roots = self.get_roots_fallback().await;
             tracing::error!(
-                "MCP client returned no valid root paths, Falling back to current working directory: {roots:?}"
+                "MCP client returned no valid root paths, Falling back to default root paths: {roots:?}"
             );
-            return roots;
+            return (roots, true);
         }
 
+        if self.access_policy.client_roots == ClientRootsMode::Augment {
+            roots.extend(self.access_policy.roots.iter().cloned());
+        }
         tracing::info!("Finalized MCP root paths: {roots:?}");
-        roots
+        (roots, true)
     }
 
+    /// Returns the configured root directories, or the current working directory if there are
+    /// none.
     #[tracing::instrument(skip_all)]
-    async fn get_roots_fallback() -> IndexSet<PathBuf> {
+    async fn get_roots_fallback(&self) -> IndexSet<PathBuf> {
+        if !self.access_policy.roots.is_empty() {
+            return self.access_policy.roots.clone();
+        }
         if let Ok(cwd) = std::env::current_dir() {
             IndexSet::from([match tokio::fs::canonicalize(&cwd).await {
                 Ok(path) => {
-                    tracing::info!("Resolved MCP root path: {cwd:?} → {path:?}");
+                    tracing::debug!("Resolved MCP root path: {cwd:?} → {path:?}");
                     path
                 }
                 Err(err) => {
@@ -138,7 +370,7 @@ impl PdflensService {
     }
 
     #[instrument(skip_all)]
-    async fn load_file(&self, uri: &str, peer: &Peer<RoleServer>) -> Result<Vec<u8>> {
+    async fn resolve_file(&self, uri: &str, peer: &Peer<RoleServer>) -> Result<PathBuf> {
         let parse_as_uri = Url::parse(uri)
             .ok()
             .filter(|uri| uri.scheme().eq_ignore_ascii_case("file"))
@@ -146,17 +378,24 @@ impl PdflensService {
         let parse_as_path = Path::new(uri);
         let path = parse_as_uri.as_deref().unwrap_or(parse_as_path);
 
-        let roots = Self::get_roots(peer).await;
+        let roots = self.get_roots(peer).await;
 
         if parse_as_uri.is_some() || path.is_absolute() {
             let real_path = match tokio::fs::canonicalize(path).await {
                 Ok(real_path) => real_path,
                 Err(err) => {
                     if err.kind() == std::io::ErrorKind::NotFound {
+                        let relative_path = roots
+                            .iter()
+                            .find_map(|root| path.strip_prefix(root).ok())
+                            .unwrap_or(path);
+                        let suggestions =
+                            self.suggest_pdf_paths(relative_path, roots.clone()).await;
                         bail!(std::io::Error::new(
                             std::io::ErrorKind::NotFound,
                             format!(
-                                "File not found: {uri:?}\nPlease check the directory listing to confirm the correct path."
+                                "File not found: {uri:?}{suggestions}\n{}",
+                                self.find_path_hint()
                             )
                         ));
                     } else {
@@ -164,7 +403,14 @@ impl PdflensService {
                     }
                 }
             };
-            if !roots.iter().any(|root| real_path.starts_with(root)) {
+            let lexical_path = normalize_lexically(path);
+            let mut containing_roots = Vec::new();
+            for root in &roots {
+                if let Ok(Some(_)) = self.resolve_in_root(path, root).await {
+                    containing_roots.push(root);
+                }
+            }
+            if containing_roots.is_empty() {
                 let real_path_uri = Url::from_file_path(&real_path);
                 let real_path_str = if let Ok(uri) = &real_path_uri {
                     Cow::from(uri.as_str())
@@ -179,12 +425,23 @@ impl PdflensService {
                     )
                 ))
             }
-            let file_data = tokio::fs::read(real_path).await?;
-            Ok(file_data)
+            if containing_roots.into_iter().any(|root| {
+                self.access_policy.is_denied(&real_path, root)
+                    || self.access_policy.is_denied(&lexical_path, root)
+            }) {
+                bail!(Self::denied_error(uri));
+            }
+            Ok(real_path)
         } else {
+            // Nested or overlapping roots may lead to the same file, which is not ambiguous
+            let mut candidates = IndexSet::new();
+            let mut is_denied = false;
             for root in &roots {
-                let real_path = match tokio::fs::canonicalize(root.join(path)).await {
-                    Ok(real_path) => real_path,
+                let path_in_root = root.join(path);
+                let real_path = match self.resolve_in_root(&path_in_root, root).await {
+                    Ok(Some(real_path)) => real_path,
+                    // Treat as not found
+                    Ok(None) => continue,
                     Err(err) => {
                         if err.kind() == std::io::ErrorKind::NotFound {
                             continue;
@@ -193,52 +450,339 @@ impl PdflensService {
                         }
                     }
                 };
-                if !real_path.starts_with(root) {
-                    // Treat as not found
+                let lexical_path = normalize_lexically(&path_in_root);
+                if self.access_policy.is_denied(&real_path, root)
+                    || self.access_policy.is_denied(&lexical_path, root)
+                {
+                    is_denied = true;
                     continue;
                 }
-                let file_data = tokio::fs::read(real_path).await?;
-                return Ok(file_data);
+                candidates.insert(real_path);
+            }
+            if candidates.len() > 1 {
+                bail!(AmbiguousPathError {
+                    path: path.to_owned(),
+                    candidates: candidates.into_iter().collect(),
+                });
+            }
+            if let Some(real_path) = candidates.pop() {
+                return Ok(real_path);
+            }
+            if is_denied {
+                bail!(Self::denied_error(uri));
             }
+            let suggestions = self.suggest_pdf_paths(path, roots.clone()).await;
             bail!(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 format!(
-                    "File not found: {path:?}\nPlease check the directory listing to confirm the correct path. The path should be either absolute or relative to any of the user’s current workspace directories:\n{}",
+                    "File not found: {path:?}{suggestions}\n{} The path should be either absolute or relative to any of the user’s current workspace directories:\n{}",
+                    self.find_path_hint(),
                     Self::format_roots_as_uri(roots)
                 )
             ));
         }
     }
 
+    /// Returns the real path of a file if it is inside a root. If symbolic links may point
+    /// outside the roots, it is enough for the path to leave the root through such a link.
+    async fn resolve_in_root(&self, path: &Path, root: &Path) -> std::io::Result<Option<PathBuf>> {
+        let path = path.to_owned();
+        let root = root.to_owned();
+        let allow_symlink_escape = self.access_policy.allow_symlink_escape;
+        spawn_blocking(move || resolve_in_root(&path, &root, allow_symlink_escape))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    /// Points the model to `list_pdfs`, or to the resources if that tool is disabled.
+    fn find_path_hint(&self) -> &'static str {
+        if self.tool_config.disabled_tools.contains("list_pdfs") {
+            "Please check the resources, which list the PDFs in the user’s workspace, to confirm the correct path."
+        } else {
+            "Please call `list_pdfs` to confirm the correct path."
+        }
+    }
+
+    fn denied_error(uri: &str) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("Access denied: {uri:?}\nThe file is excluded by the server configuration."),
+        )
+    }
+
+    /// Lists similar PDFs in the roots for the “File not found” error, so the model can correct
+    /// the path without another round trip. Returns an empty string if nothing is similar.
+    #[instrument(skip_all)]
+    async fn suggest_pdf_paths(&self, relative_path: &Path, roots: IndexSet<PathBuf>) -> String {
+        let Ok(pdfs) = self.find_pdfs(roots, PdfFilter::default(), None).await else {
+            return String::new();
+        };
+        let suggestions = suggest_pdf_paths(&pdfs, relative_path)
+            .into_iter()
+            .map(|pdf| path_to_uri(&pdf.path).into_owned())
+            .collect::<Vec<_>>();
+        if suggestions.is_empty() {
+            return String::new();
+        }
+        let mut builder = "\nDid you mean:".to_owned();
+        for suggestion in suggestions {
+            builder.push_str("\n* ");
+            builder.push_str(&suggestion);
+        }
+        builder
+    }
+
+    /// Walks the root directories for PDFs, skipping the paths denied by the access policy.
+    async fn find_pdfs(
+        &self,
+        roots: IndexSet<PathBuf>,
+        filter: PdfFilter,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<WorkspacePdf>> {
+        let access_policy = self.access_policy.clone();
+        Ok(spawn_blocking(move || find_pdfs(&roots, &filter, max_depth, &access_policy)).await?)
+    }
+
+    #[instrument(skip_all)]
+    async fn load_pdf(
+        &self,
+        uri: &str,
+        password: Option<&str>,
+        peer: &Peer<RoleServer>,
+    ) -> Result<Arc<LoadedPdf>> {
+        let path = self.resolve_file(uri, peer).await?;
+        self.load_pdf_at(path, password, Some(peer)).await
+    }
+
+    /// Loads a local PDF without checking the roots, for the command-line subcommands, where the
+    /// user names the file directly. Not part of the library API, as it bypasses the access
+    /// policy.
+    #[doc(hidden)]
+    pub async fn open_pdf(&self, path: &Path, password: Option<&str>) -> Result<Arc<LoadedPdf>> {
+        let path = tokio::fs::canonicalize(path)
+            .await
+            .map_err(|err| eyre!("Failed to open {path:?}: {err}"))?;
+        self.load_pdf_at(path, password, None).await
+    }
+
+    /// Loads a PDF from a path that is already checked to be inside the roots.
+    ///
+    /// If `elicitation_peer` is given, the user may be asked for the password of an encrypted PDF.
+    #[instrument(skip_all)]
+    async fn load_pdf_at(
+        &self,
+        path: PathBuf,
+        password: Option<&str>,
+        elicitation_peer: Option<&Peer<RoleServer>>,
+    ) -> Result<Arc<LoadedPdf>> {
+        let metadata = tokio::fs::metadata(&path).await?;
+        let mut password = password.map(str::to_owned).or_else(|| {
+            self.pdf_passwords
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(&path)
+                .cloned()
+        });
+        if let Some(pdf) = self.pdf_cache.get(&path, &metadata, password.as_deref()) {
+            tracing::debug!("Loaded PDF from cache: {path:?}");
+            return Ok(pdf);
+        }
+
+        let file_data = self.read_pdf_file(&path).await?;
+        let mut num_elicitations = 0;
+        loop {
+            let result = spawn_blocking({
+                let file_data = file_data.clone();
+                let password = password.clone();
+                move || Self::parse_pdf(file_data, password.as_deref())
+            })
+            .await?;
+            let err = match result {
+                Ok((pdf, encrypted)) => {
+                    let password = password.filter(|_| encrypted);
+                    let pdf = Arc::new(LoadedPdf {
+                        pdf,
+                        data: file_data,
+                        password: password.clone(),
+                    });
+                    if let Some(password) = &password {
+                        self.pdf_passwords
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .insert(path.clone(), password.clone());
+                    }
+                    self.pdf_cache
+                        .insert(path, &metadata, password, pdf.clone());
+                    return Ok(pdf);
+                }
+                Err(err) => err,
+            };
+
+            let Some(password_err) = err.downcast_ref::<PdfPasswordError>() else {
+                return Err(err);
+            };
+            let Some(peer) = elicitation_peer.filter(|peer| {
+                peer.supported_elicitation_modes()
+                    .contains(&ElicitationMode::Form)
+            }) else {
+                return Err(err);
+            };
+            if num_elicitations >= MAX_PASSWORD_ELICITATIONS {
+                return Err(err);
+            }
+            num_elicitations += 1;
+            password = Some(Self::elicit_password(&path, password_err, peer).await?);
+        }
+    }
+
+    /// Loads a PDF for a scan over the workspace, only if it opens without a password.
+    ///
+    /// Passwords entered in this session are never used, because the results of a scan may be
+    /// stored on disk or shared with other sessions. Encrypted PDFs fail with
+    /// `PdfPasswordError::Required`. The PDF is not added to the cache, so that a scan does not
+    /// evict the PDFs being read.
+    #[instrument(skip_all)]
+    async fn load_pdf_for_scan(&self, path: PathBuf) -> Result<Arc<LoadedPdf>> {
+        let metadata = tokio::fs::metadata(&path).await?;
+        // PDFs cached with a password are only returned to callers with the same password
+        if let Some(pdf) = self.pdf_cache.peek(&path, &metadata, None) {
+            tracing::debug!("Loaded PDF from cache: {path:?}");
+            return Ok(pdf);
+        }
+        let file_data = self.read_pdf_file(&path).await?;
+        let (pdf, _) = spawn_blocking({
+            let file_data = file_data.clone();
+            move || Self::parse_pdf(file_data, None)
+        })
+        .await??;
+        let pdf = Arc::new(LoadedPdf {
+            pdf,
+            data: file_data,
+            password: None,
+        });
+        Ok(pdf)
+    }
+
+    /// Reads a PDF through the file policy, given its canonical path.
+    async fn read_pdf_file(&self, path: &Path) -> Result<Arc<Vec<u8>>> {
+        let access_policy = self.access_policy.clone();
+        let path = path.to_owned();
+        let file_data =
+            spawn_blocking(move || access_policy.file_policy().read_file(&path)).await??;
+        Ok(Arc::new(file_data))
+    }
+
+    /// Asks the user for the password through MCP elicitation, so it never appears in the chat.
+    #[instrument(skip_all)]
+    async fn elicit_password(
+        path: &Path,
+        reason: &PdfPasswordError,
+        peer: &Peer<RoleServer>,
+    ) -> Result<String> {
+        let file_name = path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy())
+            .unwrap_or_else(|| path.to_string_lossy());
+        let message = match reason {
+            PdfPasswordError::Required => {
+                format!("“{file_name}” is encrypted. Please enter its password.")
+            }
+            PdfPasswordError::Incorrect => {
+                format!("The password for “{file_name}” is incorrect. Please try again.")
+            }
+        };
+        match peer.elicit::<PdfPasswordElicitation>(message).await {
+            Ok(Some(PdfPasswordElicitation { password })) => Ok(password),
+            Ok(None) | Err(ElicitationError::UserDeclined | ElicitationError::UserCancelled) => {
+                bail!("The user declined to enter the password for the encrypted PDF.")
+            }
+            Err(err) => bail!("Failed to ask the user for the password: {err}"),
+        }
+    }
+
+    /// Returns the parsed PDF, and whether a password was needed to open it.
+    #[instrument(skip_all)]
+    pub(crate) fn parse_pdf(
+        file_data: Arc<Vec<u8>>,
+        password: Option<&str>,
+    ) -> Result<(Pdf, bool)> {
+        let err = match Pdf::new(file_data.clone()) {
+            Ok(pdf) => return Ok((pdf, false)),
+            Err(err) => err,
+        };
+        match (err, password) {
+            (LoadPdfError::Decryption(DecryptionError::PasswordProtected), Some(password)) => {
+                match Pdf::new_with_password(file_data, password) {
+                    Ok(pdf) => Ok((pdf, true)),
+                    Err(LoadPdfError::Decryption(DecryptionError::PasswordProtected)) => {
+                        bail!(PdfPasswordError::Incorrect)
+                    }
+                    Err(LoadPdfError::Decryption(err)) => bail!("Failed to decrypt PDF: {err:?}"),
+                    Err(err) => bail!("Failed to load PDF: {err:?}"),
+                }
+            }
+            (LoadPdfError::Decryption(DecryptionError::PasswordProtected), None) => {
+                bail!(PdfPasswordError::Required)
+            }
+            (LoadPdfError::Decryption(err), _) => bail!("Failed to decrypt PDF: {err:?}"),
+            (err, _) => bail!("Failed to load PDF: {err:?}"),
+        }
+    }
+
     #[instrument(skip_all)]
     async fn get_pdf_num_pages_handler(
         &self,
         params: GetPdfNumPagesParams,
         context: RequestContext<RoleServer>,
     ) -> Result<Json<GetPdfNumPagesResult>> {
-        let file_data = Arc::new(self.load_file(&params.path, &context.peer).await?);
-        let pdf = spawn_blocking(|| {
-            Pdf::new(file_data).map_err(|err| eyre!("Failed to load PDF: {err:?}"))
-        })
-        .await??;
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
         let num_pages = pdf.pages().len();
         Ok(Json(GetPdfNumPagesResult { num_pages }))
     }
 
-    #[allow(dead_code)]
+    #[instrument(skip_all)]
+    async fn get_pdf_metadata_handler(
+        &self,
+        params: GetPdfMetadataParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Json<GetPdfMetadataResult>> {
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
+        let include_xmp_packet = params.include_xmp_packet;
+        let metadata =
+            spawn_blocking(move || read_metadata(&pdf, pdf.password.is_some(), include_xmp_packet))
+                .await?;
+        Ok(Json(metadata))
+    }
+
+    #[instrument(skip_all)]
+    async fn get_pdf_outline_handler(
+        &self,
+        params: GetPdfOutlineParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Json<GetPdfOutlineResult>> {
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
+        let mut items = spawn_blocking(move || read_outline(&pdf)).await?;
+        if let Some(max_level) = params.max_level {
+            items.retain(|item| item.level <= max_level);
+        }
+        Ok(Json(GetPdfOutlineResult { items }))
+    }
+
     #[instrument(skip_all)]
     async fn read_pdf_as_images_handler(
         &self,
         params: ReadPdfAsImagesParams,
         context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult> {
-        let file_data = Arc::new(self.load_file(&params.path, &context.peer).await?);
-        let pdf = spawn_blocking(|| match Pdf::new(file_data) {
-            Ok(ok) => Ok(Arc::new(ok)),
-            Err(err) => bail!("Failed to load PDF: {err:?}"),
-        })
-        .await??;
-        let interpreter_settings = InterpreterSettings::default();
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
 
         // Convert to 0-based, half-closed half-open indices
         let num_pages = pdf.pages().len();
@@ -268,42 +812,15 @@ impl PdflensService {
             };
 
             let pdf = pdf.clone();
-            let image_dimension = params.image_dimension;
-            let interpreter_settings = interpreter_settings.clone();
+            let image_dimension = params
+                .image_dimension
+                .unwrap_or(self.tool_config.image_dimension);
 
+            let pdf_workers = self.pdf_workers.clone();
+            let ct = context.ct.clone();
             let image = spawn_blocking(move || {
-                let page = &pdf.pages()[page_idx];
-
-                let (orig_width, orig_height) = page.render_dimensions();
-                let render_settings = if orig_width >= orig_height {
-                    let width = image_dimension.max(1);
-                    let height = ((image_dimension as f64 * orig_height as f64 / orig_width as f64)
-                        .round() as u16)
-                        .max(1);
-                    RenderSettings {
-                        x_scale: width as f32 / orig_width,
-                        y_scale: height as f32 / orig_height,
-                        width: Some(width),
-                        height: Some(height),
-                        bg_color: WHITE,
-                    }
-                } else {
-                    let width = ((image_dimension as f64 * orig_width as f64 / orig_height as f64)
-                        .round() as u16)
-                        .max(1);
-                    let height = image_dimension.max(1);
-                    RenderSettings {
-                        x_scale: width as f32 / orig_width,
-                        y_scale: height as f32 / orig_height,
-                        width: Some(width),
-                        height: Some(height),
-                        bg_color: WHITE,
-                    }
-                };
-
-                eyre::Ok(BASE64_STANDARD.encode(
-                    hayro::render(page, &interpreter_settings, &render_settings).into_png()?,
-                ))
+                let image = pdf_workers.render_page_png(&pdf, page_idx, image_dimension, &ct)?;
+                eyre::Ok(BASE64_STANDARD.encode(image))
             })
             .await??;
 
@@ -325,35 +842,191 @@ impl PdflensService {
         Ok(CallToolResult::success(content))
     }
 
+    /// Renders a page to PNG, given its 1-based page number, like the `read_pdf_page_as_image`
+    /// tool. `image_dimension` defaults to the configured one.
+    #[instrument(skip_all)]
+    pub async fn render_page(
+        &self,
+        pdf: Arc<LoadedPdf>,
+        page_num: usize,
+        image_dimension: Option<u16>,
+    ) -> Result<Vec<u8>> {
+        let image_dimension = image_dimension.unwrap_or(self.tool_config.image_dimension);
+        let pdf_workers = self.pdf_workers.clone();
+        spawn_blocking(move || {
+            let num_pages = pdf.pages().len();
+            let Some(page_idx) = page_num.checked_sub(1).filter(|&x| x < num_pages) else {
+                bail!("Page number {page_num} is out of range (1–{num_pages})");
+            };
+
+            pdf_workers.render_page_png(&pdf, page_idx, image_dimension, &CancellationToken::new())
+        })
+        .await?
+    }
+
+    /// Renders a page to PNG, scaled so that its longer side is `image_dimension` pixels.
+    pub(crate) fn render_page_png(page: &Page<'_>, image_dimension: u16) -> Result<Vec<u8>> {
+        let interpreter_settings = InterpreterSettings::default();
+
+        let (orig_width, orig_height) = page.render_dimensions();
+        let render_settings = if orig_width >= orig_height {
+            let width = image_dimension.max(1);
+            let height = ((image_dimension as f64 * orig_height as f64 / orig_width as f64).round()
+                as u16)
+                .max(1);
+            RenderSettings {
+                x_scale: width as f32 / orig_width,
+                y_scale: height as f32 / orig_height,
+                width: Some(width),
+                height: Some(height),
+                bg_color: WHITE,
+            }
+        } else {
+            let width = ((image_dimension as f64 * orig_width as f64 / orig_height as f64).round()
+                as u16)
+                .max(1);
+            let height = image_dimension.max(1);
+            RenderSettings {
+                x_scale: width as f32 / orig_width,
+                y_scale: height as f32 / orig_height,
+                width: Some(width),
+                height: Some(height),
+                bg_color: WHITE,
+            }
+        };
+
+        Ok(hayro::render(page, &interpreter_settings, &render_settings).into_png()?)
+    }
+
     #[instrument(skip_all)]
     async fn read_pdf_as_text_handler(
         &self,
         params: ReadPdfAsTextParams,
         context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult> {
-        let file_data = Arc::new(self.load_file(&params.path, &context.peer).await?);
-        let pdf = spawn_blocking(|| match Pdf::new(file_data) {
-            Ok(ok) => Ok(Arc::new(ok)),
-            Err(err) => bail!("Failed to load PDF: {err:?}"),
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
+        let content = self
+            .read_text(&pdf, params.from_page, params.to_page, Some(&context))
+            .await?;
+
+        Ok(CallToolResult::success(vec![
+            Content::text(content).with_audience(vec![Role::Assistant]),
+        ]))
+    }
+
+    #[instrument(skip_all)]
+    async fn read_pdf_section_handler(
+        &self,
+        params: ReadPdfSectionParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult> {
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
+        let num_pages = pdf.pages().len();
+        let items = spawn_blocking({
+            let pdf = pdf.clone();
+            move || read_outline(&pdf)
         })
-        .await??;
+        .await?;
+        if items.is_empty() {
+            bail!("The PDF has no outline. Please use `read_pdf_as_text` instead.");
+        }
+
+        let item_idx = find_section(&items, &params.section)?;
+        let page_range = section_page_range(&items, item_idx, num_pages).ok_or_else(|| {
+            eyre!(
+                "The section {:?} does not point to a page in this PDF.",
+                items[item_idx].title
+            )
+        })?;
+        let summary = format!(
+            "Section {:?} spans pages {}–{}.",
+            items[item_idx].title,
+            page_range.start + 1,
+            page_range.end
+        );
+        let content = self.extract_text(&pdf, page_range, Some(&context)).await?;
+
+        Ok(CallToolResult::success(vec![
+            Content::text(summary).with_audience(vec![Role::Assistant]),
+            Content::text(content).with_audience(vec![Role::Assistant]),
+        ]))
+    }
 
+    /// Extracts text from a range of 1-based page numbers, like the `read_pdf_as_text` tool.
+    #[instrument(skip_all)]
+    pub async fn read_text(
+        &self,
+        pdf: &Arc<LoadedPdf>,
+        from_page: usize,
+        to_page: Option<usize>,
+        context: Option<&RequestContext<RoleServer>>,
+    ) -> Result<String> {
         // Convert to 0-based, half-closed half-open indices
         let num_pages = pdf.pages().len();
-        let from_page_idx = params.from_page.saturating_sub(1).min(num_pages);
-        let to_page_idx = params
-            .to_page
+        let from_page_idx = from_page.saturating_sub(1).min(num_pages);
+        let to_page_idx = to_page
             .map(|x| x.clamp(from_page_idx, num_pages))
             .unwrap_or(num_pages);
-        let page_count = to_page_idx - from_page_idx;
+        self.extract_text(pdf, from_page_idx..to_page_idx, context)
+            .await
+    }
 
-        let progress_token = context.meta.get_progress_token();
+    /// Extracts text from a range of 0-based page indices, separating each page by “\x0c”. A page
+    /// whose text cannot be extracted is replaced by a note with the error.
+    ///
+    /// Progress is only reported, and cancellation only checked, if `context` is given.
+    #[instrument(skip_all)]
+    async fn extract_text(
+        &self,
+        pdf: &Arc<LoadedPdf>,
+        page_range: Range<usize>,
+        context: Option<&RequestContext<RoleServer>>,
+    ) -> Result<String> {
+        let from_page_idx = page_range.start;
         let mut content = String::new();
-        for (i, page_idx) in (from_page_idx..to_page_idx)
-            .enumerate()
-            .take_while(|_| !context.ct.is_cancelled())
-        {
-            if let Some(progress_token) = &progress_token {
+        self.for_each_page_text(pdf, page_range, context, |page_idx, text| {
+            if page_idx != from_page_idx {
+                content.push('\x0c');
+            }
+            match text {
+                Ok(text) => content.push_str(&text),
+                // Only this page is lost, e.g. if a worker crashed on it
+                Err(err) => {
+                    tracing::warn!("Failed to extract text from page {}: {err}", page_idx + 1);
+                    content.push_str(&format!("[Page {} could not be read: {err}]", page_idx + 1));
+                }
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(content)
+    }
+
+    /// Extracts text from a range of 0-based page indices, and calls `on_page` with the index and
+    /// the text of each page. Stops at the first error that `on_page` returns.
+    ///
+    /// Progress is only reported, and cancellation only checked, if `context` is given.
+    async fn for_each_page_text(
+        &self,
+        pdf: &Arc<LoadedPdf>,
+        page_range: Range<usize>,
+        context: Option<&RequestContext<RoleServer>>,
+        mut on_page: impl FnMut(usize, Result<String>) -> Result<()>,
+    ) -> Result<()> {
+        let page_count = page_range.len();
+
+        let progress_token = context.and_then(|context| context.meta.get_progress_token());
+        let ct = context
+            .map(|context| context.ct.clone())
+            .unwrap_or_default();
+        for (i, page_idx) in page_range.enumerate().take_while(|_| !ct.is_cancelled()) {
+            if let Some(context) = context
+                && let Some(progress_token) = &progress_token
+            {
                 context
                     .peer
                     .notify_progress(ProgressNotificationParam {
@@ -365,23 +1038,17 @@ impl PdflensService {
                     .await?;
             };
 
-            if i != 0 {
-                content.push('\x0c');
-            }
-
             let pdf = pdf.clone();
-            let new_content = spawn_blocking(move || {
-                let mut text = content;
-                let mut device = PlainTextOutput::new(&mut text);
-                output_doc_page(&pdf, &mut device, u32::try_from(page_idx)? + 1)?;
-                eyre::Ok(text)
-            })
-            .await??;
-
-            content = new_content;
+            let pdf_workers = self.pdf_workers.clone();
+            let ct = ct.clone();
+            let text =
+                spawn_blocking(move || pdf_workers.extract_page_text(&pdf, page_idx, &ct)).await?;
+            on_page(page_idx, text)?;
         }
 
-        if let Some(progress_token) = &progress_token {
+        if let Some(context) = context
+            && let Some(progress_token) = &progress_token
+        {
             context
                 .peer
                 .notify_progress(ProgressNotificationParam {
@@ -393,79 +1060,907 @@ impl PdflensService {
                 .await?;
         };
 
-        Ok(CallToolResult::success(vec![
-            Content::text(content).with_audience(vec![Role::Assistant]),
-        ]))
+        Ok(())
     }
 
     #[instrument(skip_all)]
-    async fn read_pdf_page_as_image_handler(
+    async fn search_pdf_handler(
         &self,
-        params: ReadPdfPageAsImageParams,
+        params: SearchPdfParams,
         context: RequestContext<RoleServer>,
-    ) -> Result<CallToolResult> {
-        let file_data = Arc::new(self.load_file(&params.path, &context.peer).await?);
-        let pdf = spawn_blocking(|| match Pdf::new(file_data) {
-            Ok(ok) => Ok(Arc::new(ok)),
-            Err(err) => bail!("Failed to load PDF: {err:?}"),
-        })
-        .await??;
-
-        let page_num = params.page;
-        let image_dimension = params.image_dimension;
+    ) -> Result<Json<SearchPdfResult>> {
+        let query = SearchQuery::new(
+            &params.query,
+            params.regex,
+            params.ignore_case,
+            params.ignore_whitespace,
+        )?;
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
 
-        let image = spawn_blocking(move || {
-            let pages = pdf.pages();
-            let Some(page) = page_num.checked_sub(1).and_then(|x| pages.get(x)) else {
-                bail!(
-                    "Page number {} is out of range (1–{})",
-                    page_num,
-                    pages.len()
-                );
-            };
+        // Convert to 0-based, half-closed half-open indices
+        let num_pages = pdf.pages().len();
+        let from_page_idx = params.from_page.saturating_sub(1).min(num_pages);
+        let to_page_idx = params
+            .to_page
+            .map(|x| x.clamp(from_page_idx, num_pages))
+            .unwrap_or(num_pages);
 
-            let interpreter_settings = InterpreterSettings::default();
-
-            let (orig_width, orig_height) = page.render_dimensions();
-            let render_settings = if orig_width >= orig_height {
-                let width = image_dimension.max(1);
-                let height = ((image_dimension as f64 * orig_height as f64 / orig_width as f64)
-                    .round() as u16)
-                    .max(1);
-                RenderSettings {
-                    x_scale: width as f32 / orig_width,
-                    y_scale: height as f32 / orig_height,
-                    width: Some(width),
-                    height: Some(height),
-                    bg_color: WHITE,
-                }
-            } else {
-                let width = ((image_dimension as f64 * orig_width as f64 / orig_height as f64)
-                    .round() as u16)
-                    .max(1);
-                let height = image_dimension.max(1);
-                RenderSettings {
-                    x_scale: width as f32 / orig_width,
-                    y_scale: height as f32 / orig_height,
-                    width: Some(width),
-                    height: Some(height),
-                    bg_color: WHITE,
+        let mut result = SearchPdfResult {
+            total_matches: 0,
+            pages: Vec::new(),
+            failed_pages: Vec::new(),
+        };
+        self.for_each_page_text(
+            &pdf,
+            from_page_idx..to_page_idx,
+            Some(&context),
+            |page_idx, text| {
+                // One broken page should not fail the whole search
+                match text {
+                    Ok(text) => {
+                        let matches =
+                            query.search(&text, params.context_chars, params.max_snippets_per_page);
+                        if matches.match_count != 0 {
+                            result.total_matches += matches.match_count;
+                            result.pages.push(PdfSearchPageHit {
+                                page: page_idx + 1,
+                                match_count: matches.match_count,
+                                snippets: matches.snippets,
+                            });
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            "Failed to extract text from page {} of {:?}: {err}",
+                            page_idx + 1,
+                            params.path
+                        );
+                        result.failed_pages.push(page_idx + 1);
+                    }
                 }
-            };
-
-            Ok(BASE64_STANDARD
-                .encode(hayro::render(page, &interpreter_settings, &render_settings).into_png()?))
-        })
-        .await??;
+                Ok(())
+            },
+        )
+        .await?;
 
-        Ok(CallToolResult::success(vec![
-            Content::image(image, "image/png").with_audience(vec![Role::Assistant]),
-        ]))
+        Ok(Json(result))
+    }
+
+    #[instrument(skip_all)]
+    async fn search_workspace_pdfs_handler(
+        &self,
+        params: SearchWorkspacePdfsParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Json<SearchWorkspacePdfsResult>> {
+        let query = SearchQuery::new(
+            &params.query,
+            params.regex,
+            params.ignore_case,
+            params.ignore_whitespace,
+        )?;
+        let filter = PdfFilter::new(&params.include, &params.exclude)?;
+        let roots = self.get_roots(&context.peer).await;
+        let pdfs = self.find_pdfs(roots, filter, None).await?;
+        let file_count = pdfs.len();
+
+        let progress_token = context.meta.get_progress_token();
+        let mut files = Vec::new();
+        let mut failed_files = Vec::new();
+        for (i, workspace_pdf) in pdfs
+            .into_iter()
+            .enumerate()
+            .take_while(|_| !context.ct.is_cancelled())
+        {
+            if let Some(progress_token) = &progress_token {
+                context
+                    .peer
+                    .notify_progress(ProgressNotificationParam {
+                        progress_token: progress_token.clone(),
+                        progress: i as f64,
+                        total: Some(file_count as f64),
+                        message: Some(workspace_pdf.relative_path.to_string_lossy().into_owned()),
+                    })
+                    .await?;
+            };
+
+            let uri = path_to_uri(&workspace_pdf.path).into_owned();
+            // Never ask for passwords here, as there could be many encrypted PDFs
+            let pdf = match self.load_pdf_for_scan(workspace_pdf.path).await {
+                Ok(pdf) => pdf,
+                Err(err) => {
+                    tracing::warn!("Failed to load PDF: {uri:?} → {err}");
+                    failed_files.push(WorkspacePdfFailure {
+                        uri,
+                        error: format!("{err:#}"),
+                    });
+                    continue;
+                }
+            };
+
+            let pdf_workers = self.pdf_workers.clone();
+            let query = query.clone();
+            let ct = context.ct.clone();
+            let context_chars = params.context_chars;
+            let max_snippets = params.max_snippets_per_page;
+            // Entered inside the blocking thread, so the warnings reach the client
+            let span = Span::current();
+            let pages = spawn_blocking({
+                let uri = uri.clone();
+                move || {
+                    let _span = span.enter();
+                    let mut pages = Vec::new();
+                    for page_idx in (0..pdf.pages().len()).take_while(|_| !ct.is_cancelled()) {
+                        match pdf_workers.extract_page_text(&pdf, page_idx, &ct) {
+                            Ok(text) => {
+                                let matches = query.search(&text, context_chars, max_snippets);
+                                if matches.match_count != 0 {
+                                    pages.push(PdfSearchPageHit {
+                                        page: page_idx + 1,
+                                        match_count: matches.match_count,
+                                        snippets: matches.snippets,
+                                    });
+                                }
+                            }
+                            Err(err) => {
+                                tracing::warn!(
+                                    "Failed to extract text from page {} of {uri:?}: {err}",
+                                    page_idx + 1
+                                );
+                            }
+                        }
+                    }
+                    pages
+                }
+            })
+            .await?;
+
+            let match_count = pages.iter().map(|page| page.match_count).sum();
+            if match_count != 0 {
+                files.push(WorkspacePdfHit {
+                    uri,
+                    match_count,
+                    pages,
+                });
+            }
+        }
+
+        if let Some(progress_token) = &progress_token {
+            context
+                .peer
+                .notify_progress(ProgressNotificationParam {
+                    progress_token: progress_token.clone(),
+                    progress: file_count as f64,
+                    total: Some(file_count as f64),
+                    message: None,
+                })
+                .await?;
+        };
+
+        files.sort_by(|a, b| b.match_count.cmp(&a.match_count));
+        files.truncate(params.max_files);
+        for file in &mut files {
+            file.pages.truncate(params.max_pages_per_file);
+        }
+        Ok(Json(SearchWorkspacePdfsResult {
+            searched_files: file_count,
+            files,
+            failed_files,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn list_pdfs_handler(
+        &self,
+        params: ListPdfsParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Json<ListPdfsResult>> {
+        let filter = PdfFilter::new(&params.include, &params.exclude)?;
+        let roots = self.get_roots(&context.peer).await;
+        let mut pdfs = self.find_pdfs(roots, filter, params.max_depth).await?;
+        let truncated = pdfs.len() > params.max_files;
+        pdfs.truncate(params.max_files);
+        let file_count = pdfs.len();
+
+        let progress_token = context.meta.get_progress_token();
+        let mut files = Vec::with_capacity(file_count);
+        for (i, workspace_pdf) in pdfs
+            .into_iter()
+            .enumerate()
+            .take_while(|_| !context.ct.is_cancelled())
+        {
+            if params.include_num_pages
+                && let Some(progress_token) = &progress_token
+            {
+                context
+                    .peer
+                    .notify_progress(ProgressNotificationParam {
+                        progress_token: progress_token.clone(),
+                        progress: i as f64,
+                        total: Some(file_count as f64),
+                        message: Some(workspace_pdf.relative_path.to_string_lossy().into_owned()),
+                    })
+                    .await?;
+            };
+
+            let uri = path_to_uri(&workspace_pdf.path).into_owned();
+            let Ok(metadata) = tokio::fs::metadata(&workspace_pdf.path).await else {
+                continue;
+            };
+            let num_pages = if params.include_num_pages {
+                // Never ask for passwords here, as there could be many encrypted PDFs
+                match self.load_pdf_for_scan(workspace_pdf.path).await {
+                    Ok(pdf) => Some(pdf.pages().len()),
+                    Err(err) => {
+                        tracing::warn!("Failed to load PDF: {uri:?} → {err}");
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            files.push(WorkspacePdfEntry {
+                uri,
+                size: metadata.len(),
+                num_pages,
+            });
+        }
+
+        if params.include_num_pages
+            && let Some(progress_token) = &progress_token
+        {
+            context
+                .peer
+                .notify_progress(ProgressNotificationParam {
+                    progress_token: progress_token.clone(),
+                    progress: file_count as f64,
+                    total: Some(file_count as f64),
+                    message: None,
+                })
+                .await?;
+        };
+
+        Ok(Json(ListPdfsResult { files, truncated }))
+    }
+
+    /// Extracts the text of every page to add a PDF to the index. Pages that cannot be read are
+    /// indexed as empty. Returns `None` if cancelled.
+    fn index_pdf(
+        pdf_workers: &PdfWorkers,
+        pdf: &LoadedPdf,
+        metadata: &std::fs::Metadata,
+        uri: &str,
+        ct: &CancellationToken,
+    ) -> Option<IndexedFile> {
+        let mut page_texts = Vec::with_capacity(pdf.pages().len());
+        for page_idx in 0..pdf.pages().len() {
+            if ct.is_cancelled() {
+                return None;
+            }
+            let text = pdf_workers
+                .extract_page_text(pdf, page_idx, ct)
+                .unwrap_or_else(|err| {
+                    tracing::warn!(
+                        "Failed to extract text from page {} of {uri:?}: {err}",
+                        page_idx + 1
+                    );
+                    String::new()
+                });
+            page_texts.push(text);
+        }
+        Some(IndexedFile::new(
+            metadata,
+            page_texts.iter().map(String::as_str),
+        ))
+    }
+
+    #[instrument(skip_all)]
+    async fn search_pdf_index_handler(
+        &self,
+        params: SearchPdfIndexParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Json<SearchPdfIndexResult>> {
+        let mut terms = tokenize(&params.query).collect::<Vec<_>>();
+        terms.sort_unstable();
+        terms.dedup();
+        if terms.is_empty() {
+            bail!("The search query contains no words.");
+        }
+        let filter = PdfFilter::new(&params.include, &params.exclude)?;
+        let roots = self.get_roots(&context.peer).await;
+
+        let mut indexes = Vec::with_capacity(roots.len());
+        let mut stale_files = Vec::new();
+        for root in roots {
+            let index = self.pdf_index.open(&root).await;
+            let pdfs = self
+                .find_pdfs(IndexSet::from([root.clone()]), PdfFilter::default(), None)
+                .await?;
+
+            let mut index_guard = index.lock().await;
+            let relative_paths = pdfs
+                .iter()
+                .map(|pdf| pdf.relative_path.as_path())
+                .collect::<HashSet<_>>();
+            index_guard.retain(|relative_path| relative_paths.contains(relative_path));
+            for pdf in pdfs {
+                let Ok(metadata) = tokio::fs::metadata(&pdf.path).await else {
+                    continue;
+                };
+                if !index_guard.is_fresh(&pdf.relative_path, &metadata) {
+                    stale_files.push((indexes.len(), pdf, metadata));
+                }
+            }
+            drop(index_guard);
+            indexes.push((root, index));
+        }
+
+        // Bring the indexes up to date, extracting text only from new or modified PDFs
+        let progress_token = context.meta.get_progress_token();
+        let stale_file_count = stale_files.len();
+        let mut updated_files = 0;
+        let mut failed_files = Vec::new();
+        for (i, (index_idx, workspace_pdf, metadata)) in stale_files
+            .into_iter()
+            .enumerate()
+            .take_while(|_| !context.ct.is_cancelled())
+        {
+            if let Some(progress_token) = &progress_token {
+                context
+                    .peer
+                    .notify_progress(ProgressNotificationParam {
+                        progress_token: progress_token.clone(),
+                        progress: i as f64,
+                        total: Some(stale_file_count as f64),
+                        message: Some(format!(
+                            "Indexing {}",
+                            workspace_pdf.relative_path.to_string_lossy()
+                        )),
+                    })
+                    .await?;
+            };
+
+            let uri = path_to_uri(&workspace_pdf.path).into_owned();
+            let indexed_file = match self.load_pdf_for_scan(workspace_pdf.path).await {
+                Ok(pdf) => {
+                    let pdf_workers = self.pdf_workers.clone();
+                    let ct = context.ct.clone();
+                    // Entered inside the blocking thread, so the warnings reach the client
+                    let span = Span::current();
+                    spawn_blocking({
+                        let uri = uri.clone();
+                        move || {
+                            let _span = span.enter();
+                            Self::index_pdf(&pdf_workers, &pdf, &metadata, &uri, &ct)
+                        }
+                    })
+                    .await?
+                }
+                Err(err) if err.downcast_ref::<PdfPasswordError>().is_some() => {
+                    tracing::info!("Skipped encrypted PDF for indexing: {uri:?}");
+                    Some(IndexedFile::encrypted(&metadata))
+                }
+                Err(err) => {
+                    // Not recorded in the index, so it is tried again on the next search
+                    tracing::warn!("Failed to load PDF for indexing: {uri:?} → {err}");
+                    failed_files.push(WorkspacePdfFailure {
+                        uri,
+                        error: format!("{err:#}"),
+                    });
+                    continue;
+                }
+            };
+            let Some(indexed_file) = indexed_file else {
+                break;
+            };
+            indexes[index_idx]
+                .1
+                .lock()
+                .await
+                .insert(workspace_pdf.relative_path, indexed_file);
+            updated_files += 1;
+        }
+
+        if let Some(progress_token) = &progress_token {
+            context
+                .peer
+                .notify_progress(ProgressNotificationParam {
+                    progress_token: progress_token.clone(),
+                    progress: stale_file_count as f64,
+                    total: Some(stale_file_count as f64),
+                    message: None,
+                })
+                .await?;
+        };
+
+        // Only one index is locked at a time, as another session may lock the same indexes in
+        // another order. If another session updates an index in between, the scores are only
+        // slightly off.
+        for (root, index) in &indexes {
+            // Even if cancelled, keep whatever has been indexed so far
+            if let Err(err) = index.lock().await.save().await {
+                tracing::error!("Failed to save full-text index for {root:?}: {err}");
+            }
+        }
+
+        let mut stats = IndexStats {
+            num_pages: 0,
+            total_len: 0,
+        };
+        let mut doc_freqs = HashMap::<String, usize>::new();
+        for (_, index) in &indexes {
+            let index_guard = index.lock().await;
+            let index_stats = index_guard.stats();
+            stats.num_pages += index_stats.num_pages;
+            stats.total_len += index_stats.total_len;
+            for term in &terms {
+                *doc_freqs.entry(term.clone()).or_default() += index_guard.doc_freq(term);
+            }
+        }
+
+        let mut hits = Vec::new();
+        let mut encrypted_files = Vec::new();
+        let mut indexed_files = 0;
+        for (root, index) in &indexes {
+            let index_guard = index.lock().await;
+            indexed_files += index_guard.num_files();
+            encrypted_files.extend(
+                index_guard
+                    .encrypted_files()
+                    .filter(|relative_path| filter.matches(relative_path))
+                    .map(|relative_path| path_to_uri(&root.join(relative_path)).into_owned()),
+            );
+            for hit in index_guard.search(&terms, &stats, &doc_freqs) {
+                if filter.matches(&hit.relative_path) {
+                    hits.push(PdfIndexHit {
+                        uri: path_to_uri(&root.join(&hit.relative_path)).into_owned(),
+                        page: hit.page_idx + 1,
+                        score: hit.score,
+                        matched_terms: hit.matched_terms,
+                    });
+                }
+            }
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(params.max_results);
+
+        Ok(Json(SearchPdfIndexResult {
+            indexed_files,
+            updated_files,
+            hits,
+            encrypted_files,
+            failed_files,
+        }))
+    }
+
+    fn pdf_watcher(&self, peer: &Peer<RoleServer>) -> Result<Arc<PdfWatcher>> {
+        let mut pdf_watcher = self
+            .pdf_watcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(pdf_watcher) = &*pdf_watcher {
+            return Ok(pdf_watcher.clone());
+        }
+        let new_watcher = Arc::new(PdfWatcher::new(
+            peer.clone(),
+            self.pdf_cache.clone(),
+            self.pdf_list_cache.clone(),
+        )?);
+        *pdf_watcher = Some(new_watcher.clone());
+        Ok(new_watcher)
+    }
+
+    /// Reads the text of a PDF to embed in a prompt, up to `MAX_PROMPT_PAGES` pages. The page
+    /// numbers come from prompt arguments, which are always strings.
+    #[instrument(skip_all)]
+    async fn read_prompt_document(
+        &self,
+        path: &str,
+        from_page: Option<&str>,
+        to_page: Option<&str>,
+        context: &RequestContext<RoleServer>,
+    ) -> Result<String> {
+        let parse_page_num = |page_num: Option<&str>| {
+            page_num
+                .map(str::trim)
+                .filter(|page_num| !page_num.is_empty())
+                .map(|page_num| {
+                    page_num
+                        .parse::<usize>()
+                        .map_err(|_| eyre!("Invalid page number: {page_num:?}"))
+                })
+                .transpose()
+        };
+        let from_page = parse_page_num(from_page)?.unwrap_or(1);
+        let to_page = parse_page_num(to_page)?;
+        let pdf = self.load_pdf(path, None, &context.peer).await?;
+
+        // Convert to 0-based, half-closed half-open indices
+        let num_pages = pdf.pages().len();
+        let from_page_idx = from_page.saturating_sub(1).min(num_pages);
+        let to_page_idx = to_page
+            .map(|x| x.clamp(from_page_idx, num_pages))
+            .unwrap_or(num_pages);
+        if from_page_idx == to_page_idx {
+            bail!("No pages to read. The PDF has {num_pages} pages.");
+        }
+        let is_truncated = to_page_idx - from_page_idx > MAX_PROMPT_PAGES;
+        let to_page_idx = to_page_idx.min(from_page_idx + MAX_PROMPT_PAGES);
+        let text = self
+            .extract_text(&pdf, from_page_idx..to_page_idx, Some(context))
+            .await?;
+
+        let mut document = format!(
+            "<document path={path:?} pages=\"{}–{to_page_idx}\">\n{text}\n</document>",
+            from_page_idx + 1
+        );
+        if is_truncated {
+            document.push_str(&format!(
+                "\n(Truncated: the PDF has {num_pages} pages, but only {MAX_PROMPT_PAGES} pages are included, up to page {to_page_idx}.)"
+            ));
+        }
+        Ok(document)
+    }
+
+    #[instrument(skip_all)]
+    async fn summarize_pdf_prompt_handler(
+        &self,
+        params: SummarizePdfPromptParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Vec<PromptMessage>> {
+        let document = self
+            .read_prompt_document(
+                &params.path,
+                params.from_page.as_deref(),
+                params.to_page.as_deref(),
+                &context,
+            )
+            .await?;
+        Ok(vec![PromptMessage::new_text(
+            PromptMessageRole::User,
+            format!(
+                "Please summarize the following PDF. Start with a short overview, then list the key points, citing the page numbers. Each page is separated by “\x0c” (U+000C).\n\n{document}"
+            ),
+        )])
+    }
+
+    #[instrument(skip_all)]
+    async fn extract_action_items_prompt_handler(
+        &self,
+        params: ExtractActionItemsPromptParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Vec<PromptMessage>> {
+        let document = self
+            .read_prompt_document(
+                &params.path,
+                params.from_page.as_deref(),
+                params.to_page.as_deref(),
+                &context,
+            )
+            .await?;
+        Ok(vec![PromptMessage::new_text(
+            PromptMessageRole::User,
+            format!(
+                "Please extract the action items from the following PDF as a checklist. For each item, include the owner and the deadline if mentioned, and the page number. Also list the decisions that were made. Each page is separated by “\x0c” (U+000C).\n\n{document}"
+            ),
+        )])
+    }
+
+    #[instrument(skip_all)]
+    async fn compare_pdfs_prompt_handler(
+        &self,
+        params: ComparePdfsPromptParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Vec<PromptMessage>> {
+        let document1 = self
+            .read_prompt_document(&params.path1, None, None, &context)
+            .await?;
+        let document2 = self
+            .read_prompt_document(&params.path2, None, None, &context)
+            .await?;
+        Ok(vec![PromptMessage::new_text(
+            PromptMessageRole::User,
+            format!(
+                "Please compare the following two PDFs. Describe what was added, removed, and changed in the second one, citing the page numbers in both. Each page is separated by “\x0c” (U+000C).\n\n{document1}\n\n{document2}"
+            ),
+        )])
+    }
+
+    #[instrument(skip_all)]
+    async fn complete_handler(
+        &self,
+        request: CompleteRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult> {
+        // `path` in resource templates and prompts, or `path1` and `path2` in `compare_pdfs`
+        let mut values = if matches!(request.argument.name.as_str(), "path" | "path1" | "path2") {
+            let roots = self.get_roots(&context.peer).await;
+            let pdfs = match self.pdf_list_cache.get(&roots) {
+                Some(pdfs) => pdfs,
+                None => {
+                    let pdfs = Arc::new(
+                        self.find_pdfs(roots.clone(), PdfFilter::default(), None)
+                            .await?,
+                    );
+                    self.pdf_list_cache.insert(roots, pdfs.clone());
+                    pdfs
+                }
+            };
+            complete_pdf_path(&pdfs, &request.argument.value)
+        } else {
+            Vec::new()
+        };
+
+        let total = values.len();
+        values.truncate(MAX_COMPLETION_VALUES);
+        let completion = CompletionInfo::with_pagination(
+            values,
+            u32::try_from(total).ok(),
+            total > MAX_COMPLETION_VALUES,
+        )
+        .map_err(|err| eyre!(err))?;
+        Ok(CompleteResult::new(completion))
+    }
+
+    #[instrument(skip_all)]
+    async fn list_resources_handler(
+        &self,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult> {
+        let roots = self.get_roots(&context.peer).await;
+        if self.tool_config.watch_roots {
+            match self.pdf_watcher(&context.peer) {
+                Ok(pdf_watcher) => pdf_watcher.watch_roots(&roots),
+                Err(err) => tracing::warn!("Failed to watch the workspace directories: {err}"),
+            }
+        }
+        let pdfs = self.find_pdfs(roots, PdfFilter::default(), None).await?;
+
+        let resources = pdfs
+            .into_iter()
+            .map(|workspace_pdf| {
+                let mut resource = RawResource::new(
+                    path_to_uri(&workspace_pdf.path),
+                    workspace_pdf.relative_path.to_string_lossy(),
+                );
+                resource.mime_type = Some("text/plain".to_owned());
+                resource.no_annotation()
+            })
+            .collect();
+        Ok(ListResourcesResult::with_all_items(resources))
+    }
+
+    /// Reads a PDF resource, or one of its pages, as text or as PNG images.
+    #[instrument(skip_all)]
+    async fn read_resource_handler(
+        &self,
+        uri: &str,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult> {
+        let Some(resource) = PdfResourceUri::parse(uri)? else {
+            bail!(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Resource not found: {uri:?}")
+            ));
+        };
+
+        let pdf = self.load_pdf(&resource.path, None, &context.peer).await?;
+        let num_pages = pdf.pages().len();
+        let image_dimension = resource
+            .image_dimension
+            .unwrap_or(self.tool_config.image_dimension);
+
+        if let Some(page_num) = resource.page {
+            let Some(page_idx) = page_num.checked_sub(1).filter(|&x| x < num_pages) else {
+                bail!("Page number {page_num} is out of range (1–{num_pages})");
+            };
+            if !resource.render_images {
+                let content = self
+                    .extract_text(&pdf, page_idx..page_idx + 1, Some(&context))
+                    .await?;
+                return Ok(ReadResourceResult::new(vec![ResourceContents::text(
+                    content, uri,
+                )]));
+            }
+            let pdf_workers = self.pdf_workers.clone();
+            let ct = context.ct.clone();
+            let image = spawn_blocking(move || {
+                let image = pdf_workers.render_page_png(&pdf, page_idx, image_dimension, &ct)?;
+                eyre::Ok(BASE64_STANDARD.encode(image))
+            })
+            .await??;
+            return Ok(ReadResourceResult::new(vec![
+                ResourceContents::BlobResourceContents {
+                    uri: uri.to_owned(),
+                    mime_type: Some("image/png".to_owned()),
+                    blob: image,
+                    meta: None,
+                },
+            ]));
+        }
+
+        if !resource.render_images {
+            let content = self
+                .extract_text(&pdf, 0..num_pages, Some(&context))
+                .await?;
+            return Ok(ReadResourceResult::new(vec![ResourceContents::text(
+                content, uri,
+            )]));
+        }
+
+        if num_pages > MAX_RESOURCE_IMAGE_PAGES {
+            bail!(
+                "The PDF has {num_pages} pages, which is too many to read as images at once (at most {MAX_RESOURCE_IMAGE_PAGES}).\nPlease use `read_pdf_page_as_image` instead."
+            );
+        }
+        let progress_token = context.meta.get_progress_token();
+        let mut contents = Vec::with_capacity(num_pages);
+        for page_idx in (0..num_pages).take_while(|_| !context.ct.is_cancelled()) {
+            if let Some(progress_token) = &progress_token {
+                context
+                    .peer
+                    .notify_progress(ProgressNotificationParam {
+                        progress_token: progress_token.clone(),
+                        progress: page_idx as f64,
+                        total: Some(num_pages as f64),
+                        message: None,
+                    })
+                    .await?;
+            };
+
+            let pdf = pdf.clone();
+            let pdf_workers = self.pdf_workers.clone();
+            let ct = context.ct.clone();
+            let image = spawn_blocking(move || {
+                let image = pdf_workers.render_page_png(&pdf, page_idx, image_dimension, &ct)?;
+                eyre::Ok(BASE64_STANDARD.encode(image))
+            })
+            .await??;
+
+            contents.push(ResourceContents::BlobResourceContents {
+                uri: uri.to_owned(),
+                mime_type: Some("image/png".to_owned()),
+                blob: image,
+                meta: None,
+            });
+        }
+
+        if let Some(progress_token) = &progress_token {
+            context
+                .peer
+                .notify_progress(ProgressNotificationParam {
+                    progress_token: progress_token.clone(),
+                    progress: num_pages as f64,
+                    total: Some(num_pages as f64),
+                    message: None,
+                })
+                .await?;
+        };
+
+        Ok(ReadResourceResult::new(contents))
+    }
+
+    #[instrument(skip_all)]
+    async fn subscribe_handler(
+        &self,
+        uri: String,
+        context: RequestContext<RoleServer>,
+    ) -> Result<()> {
+        let Some(resource) = PdfResourceUri::parse(&uri)? else {
+            bail!(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Resource not found: {uri:?}")
+            ));
+        };
+        let path = self.resolve_file(&resource.path, &context.peer).await?;
+        let pdf_watcher = self.pdf_watcher(&context.peer)?;
+        pdf_watcher.subscribe(path, uri)?;
+        // A client that subscribes probably wants to know about new PDFs too
+        pdf_watcher.watch_roots(&self.get_roots(&context.peer).await);
+        Ok(())
+    }
+
+    fn list_resource_templates_handler() -> ListResourceTemplatesResult {
+        let templates = [
+            (
+                PAGE_TEXT_TEMPLATE,
+                "pdf_page_text",
+                "Text of one page of a PDF. `path` may be relative to the workspace directories.",
+                "text/plain",
+            ),
+            (
+                PAGE_IMAGE_TEMPLATE,
+                "pdf_page_image",
+                "One page of a PDF rendered as an image. `path` may be relative to the workspace directories. Append `?imageDimension=1024` to change the image size.",
+                "image/png",
+            ),
+            (
+                PAGE_FRAGMENT_TEMPLATE,
+                "pdf_page",
+                "Text of one page of a PDF. Also works with `file:///` URIs.",
+                "text/plain",
+            ),
+        ];
+        let resource_templates = templates
+            .into_iter()
+            .map(|(uri_template, name, description, mime_type)| {
+                let mut template = RawResourceTemplate::new(uri_template, name);
+                template.description = Some(description.to_owned());
+                template.mime_type = Some(mime_type.to_owned());
+                template.no_annotation()
+            })
+            .collect();
+        ListResourceTemplatesResult::with_all_items(resource_templates)
+    }
+
+    fn resource_error(err: eyre::Report) -> rmcp::ErrorData {
+        let message = format!("{err:#}");
+        if err.downcast_ref::<AmbiguousPathError>().is_some() {
+            return rmcp::ErrorData::invalid_params(message, None);
+        }
+        match err
+            .downcast_ref::<std::io::Error>()
+            .map(std::io::Error::kind)
+        {
+            Some(std::io::ErrorKind::NotFound) => {
+                rmcp::ErrorData::resource_not_found(message, None)
+            }
+            Some(std::io::ErrorKind::PermissionDenied) => {
+                rmcp::ErrorData::invalid_request(message, None)
+            }
+            _ => rmcp::ErrorData::internal_error(message, None),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn read_pdf_page_as_image_handler(
+        &self,
+        params: ReadPdfPageAsImageParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult> {
+        let pdf = self
+            .load_pdf(&params.path, params.password.as_deref(), &context.peer)
+            .await?;
+        let image = BASE64_STANDARD.encode(
+            self.render_page(pdf, params.page, params.image_dimension)
+                .await?,
+        );
+
+        Ok(CallToolResult::success(vec![
+            Content::image(image, "image/png").with_audience(vec![Role::Assistant]),
+        ]))
+    }
+}
+
+#[rmcp::tool_router(router = all_tools_router)]
+impl PdflensService {
+    #[rmcp::tool(
+        description = "List the PDFs in the user’s current workspace directories, with their file sizes, and optionally their numbers of pages. Use it to find the correct path before reading a PDF.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        ),
+        output_schema = schema_for_type::<ListPdfsResult>()
+    )]
+    pub async fn list_pdfs(
+        &self,
+        Parameters(params): Parameters<ListPdfsParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.list_pdfs_handler(params, context).await.map_or_else(
+            |err| {
+                tracing::error!("{err}");
+                Ok(CallToolResult::error(vec![
+                    Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                ]))
+            },
+            |ok| ok.into_call_tool_result(),
+        )
     }
-}
 
-#[rmcp::tool_router]
-impl PdflensService {
     #[rmcp::tool(
         description = "Get the number of pages in a PDF.",
         annotations(
@@ -494,17 +1989,69 @@ impl PdflensService {
             )
     }
 
-    #[cfg_attr(not(feature = "enable_multi_images"), allow(dead_code))]
-    #[cfg_attr(
-        feature = "enable_multi_images",
-        rmcp::tool(
-            description = "Read consecutive pages of a PDF as images.\nPerformance recommendation: Read in chunks of 3 pages. `read_pdf_as_text` is faster than `read_pdf_as_images`.",
-            annotations(
-                read_only_hint = true,
-                destructive_hint = false,
-                idempotent_hint = true,
-                open_world_hint = false,
-            ),
+    #[rmcp::tool(
+        description = "Get the metadata of a PDF, including its title, author, dates, PDF version, encryption status, and number of pages.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        ),
+        output_schema = schema_for_type::<GetPdfMetadataResult>()
+    )]
+    pub async fn get_pdf_metadata(
+        &self,
+        Parameters(params): Parameters<GetPdfMetadataParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.get_pdf_metadata_handler(params, context)
+            .await
+            .map_or_else(
+                |err| {
+                    tracing::error!("{err}");
+                    Ok(CallToolResult::error(vec![
+                        Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                    ]))
+                },
+                |ok| ok.into_call_tool_result(),
+            )
+    }
+
+    #[rmcp::tool(
+        description = "Get the outline (bookmarks) of a PDF with the page number of each item. Use it to find the pages of a chapter or section without reading the whole PDF.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        ),
+        output_schema = schema_for_type::<GetPdfOutlineResult>()
+    )]
+    pub async fn get_pdf_outline(
+        &self,
+        Parameters(params): Parameters<GetPdfOutlineParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.get_pdf_outline_handler(params, context)
+            .await
+            .map_or_else(
+                |err| {
+                    tracing::error!("{err}");
+                    Ok(CallToolResult::error(vec![
+                        Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                    ]))
+                },
+                |ok| ok.into_call_tool_result(),
+            )
+    }
+
+    #[rmcp::tool(
+        description = "Read consecutive pages of a PDF as images.\nPerformance recommendation: Read in chunks of 3 pages. `read_pdf_as_text` is faster than `read_pdf_as_images`.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
         )
     )]
     pub async fn read_pdf_as_images(
@@ -546,6 +2093,112 @@ impl PdflensService {
             })
     }
 
+    #[rmcp::tool(
+        description = "Read one section of a PDF in plain text format, using a title from `get_pdf_outline`. Each page is separated by “\x0c” (U+000C).\nThe section spans from the page of its title to the page before the next title at the same or a higher level, or to the page of the next title if it starts below the top of that page. So the first and last pages may also contain text from neighboring sections.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        )
+    )]
+    pub async fn read_pdf_section(
+        &self,
+        Parameters(params): Parameters<ReadPdfSectionParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.read_pdf_section_handler(params, context)
+            .await
+            .or_else(|err| {
+                tracing::error!("{err}");
+                Ok(CallToolResult::error(vec![
+                    Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                ]))
+            })
+    }
+
+    #[rmcp::tool(
+        description = "Search for text in a PDF, and get the matching page numbers with snippets around each match. Use it to find where something is mentioned before reading the pages with `read_pdf_as_text`.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        ),
+        output_schema = schema_for_type::<SearchPdfResult>()
+    )]
+    pub async fn search_pdf(
+        &self,
+        Parameters(params): Parameters<SearchPdfParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.search_pdf_handler(params, context).await.map_or_else(
+            |err| {
+                tracing::error!("{err}");
+                Ok(CallToolResult::error(vec![
+                    Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                ]))
+            },
+            |ok| ok.into_call_tool_result(),
+        )
+    }
+
+    #[rmcp::tool(
+        description = "Search for text in all PDFs in the user’s current workspace directories, and get the matching files ranked by the number of matches, with page numbers and snippets. Use it when you don’t know which PDF to read.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        ),
+        output_schema = schema_for_type::<SearchWorkspacePdfsResult>()
+    )]
+    pub async fn search_workspace_pdfs(
+        &self,
+        Parameters(params): Parameters<SearchWorkspacePdfsParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.search_workspace_pdfs_handler(params, context)
+            .await
+            .map_or_else(
+                |err| {
+                    tracing::error!("{err}");
+                    Ok(CallToolResult::error(vec![
+                        Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                    ]))
+                },
+                |ok| ok.into_call_tool_result(),
+            )
+    }
+
+    #[rmcp::tool(
+        description = "Search for words in all PDFs in the user’s current workspace directories using a full-text index, and get the most relevant pages ranked by BM25. Much faster than `search_workspace_pdfs` after the index is built, but only matches whole words.\nThe first search may take a while to build the index. Later searches only index new or modified PDFs.",
+        annotations(
+            read_only_hint = true,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false,
+        ),
+        output_schema = schema_for_type::<SearchPdfIndexResult>()
+    )]
+    pub async fn search_pdf_index(
+        &self,
+        Parameters(params): Parameters<SearchPdfIndexParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.search_pdf_index_handler(params, context)
+            .await
+            .map_or_else(
+                |err| {
+                    tracing::error!("{err}");
+                    Ok(CallToolResult::error(vec![
+                        Content::text(format!("{err:#}")).with_audience(vec![Role::Assistant]),
+                    ]))
+                },
+                |ok| ok.into_call_tool_result(),
+            )
+    }
+
     #[rmcp::tool(
         description = "Read one page of a PDF as an image. You may call this tool multiple times in parallel to read multiple pages.\nPerformance recommendation: `read_pdf_as_text` is faster than `read_pdf_page_as_image`.",
         annotations(
@@ -571,16 +2224,246 @@ impl PdflensService {
     }
 }
 
-#[rmcp::tool_handler]
+#[rmcp::prompt_router(vis = "pub")]
+impl PdflensService {
+    #[rmcp::prompt(
+        name = "summarize_pdf",
+        description = "Summarize a PDF, or a range of its pages"
+    )]
+    pub async fn summarize_pdf(
+        &self,
+        Parameters(params): Parameters<SummarizePdfPromptParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Vec<PromptMessage>, rmcp::ErrorData> {
+        self.summarize_pdf_prompt_handler(params, context)
+            .await
+            .map_err(|err| {
+                tracing::error!("{err}");
+                rmcp::ErrorData::invalid_params(format!("{err:#}"), None)
+            })
+    }
+
+    #[rmcp::prompt(
+        name = "extract_action_items",
+        description = "Extract action items, decisions, and deadlines from a range of pages in a PDF"
+    )]
+    pub async fn extract_action_items(
+        &self,
+        Parameters(params): Parameters<ExtractActionItemsPromptParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Vec<PromptMessage>, rmcp::ErrorData> {
+        self.extract_action_items_prompt_handler(params, context)
+            .await
+            .map_err(|err| {
+                tracing::error!("{err}");
+                rmcp::ErrorData::invalid_params(format!("{err:#}"), None)
+            })
+    }
+
+    #[rmcp::prompt(
+        name = "compare_pdfs",
+        description = "Compare two PDFs, such as two versions of the same document"
+    )]
+    pub async fn compare_pdfs(
+        &self,
+        Parameters(params): Parameters<ComparePdfsPromptParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<Vec<PromptMessage>, rmcp::ErrorData> {
+        self.compare_pdfs_prompt_handler(params, context)
+            .await
+            .map_err(|err| {
+                tracing::error!("{err}");
+                rmcp::ErrorData::invalid_params(format!("{err:#}"), None)
+            })
+    }
+}
+
 impl ServerHandler for PdflensService {
     #[tracing::instrument(skip_all)]
     fn get_info(&self) -> ServerInfo {
-        ServerInfo::new(ServerCapabilities::builder().enable_tools().build())
-            .with_instructions("A tool for reading PDF files")
-            .with_server_info(
-                Implementation::new("pdflens", env!("CARGO_PKG_VERSION"))
-                    .with_title("pdflens")
-                    .with_website_url("https://codeberg.org/m13253/pdflens-mcp"),
-            )
+        ServerInfo::new(
+            ServerCapabilities::builder()
+                .enable_logging()
+                .enable_completions()
+                .enable_prompts()
+                .enable_resources()
+                .enable_resources_subscribe()
+                .enable_resources_list_changed()
+                .enable_tools()
+                .build(),
+        )
+        .with_instructions("A tool for reading PDF files")
+        .with_server_info(
+            Implementation::new("pdflens", env!("CARGO_PKG_VERSION"))
+                .with_title("pdflens")
+                .with_website_url("https://codeberg.org/m13253/pdflens-mcp"),
+        )
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        self.log_sink.connect(context.peer);
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), rmcp::ErrorData> {
+        tracing::debug!("MCP log level changed: {:?}", request.level);
+        self.log_sink.set_level(request.level);
+        Ok(())
+    }
+
+    // Instead of `#[rmcp::tool_handler]` and `#[rmcp::prompt_handler]`, so that their logs are
+    // attributed to this session
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        self.tool_router
+            .call(ToolCallContext::new(self, request, context))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, rmcp::ErrorData> {
+        Ok(ListToolsResult::with_all_items(self.tool_router.list_all()))
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, rmcp::ErrorData> {
+        self.prompt_router
+            .get_prompt(PromptContext::new(
+                self,
+                request.name,
+                request.arguments,
+                context,
+            ))
+            .await
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, rmcp::ErrorData> {
+        Ok(ListPromptsResult::with_all_items(
+            self.prompt_router.list_all(),
+        ))
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn on_roots_list_changed(&self, context: NotificationContext<RoleServer>) {
+        tracing::info!("MCP root paths changed");
+        {
+            let mut roots = self.roots.lock().unwrap_or_else(PoisonError::into_inner);
+            roots.roots = None;
+            roots.generation += 1;
+        }
+        self.pdf_list_cache.invalidate();
+
+        // The resources under the roots have changed too. The new roots are fetched on the next
+        // request, which also moves the watcher over to them.
+        let is_watching_roots = self
+            .pdf_watcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|pdf_watcher| pdf_watcher.is_watching_roots());
+        if is_watching_roots && let Err(err) = context.peer.notify_resource_list_changed().await {
+            tracing::debug!("Failed to send resource list change notification: {err}");
+        }
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn complete(
+        &self,
+        request: CompleteRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, rmcp::ErrorData> {
+        self.complete_handler(request, context)
+            .await
+            .map_err(|err| {
+                tracing::error!("{err}");
+                rmcp::ErrorData::internal_error(format!("{err:#}"), None)
+            })
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, rmcp::ErrorData> {
+        self.list_resources_handler(context).await.map_err(|err| {
+            tracing::error!("{err}");
+            Self::resource_error(err)
+        })
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, rmcp::ErrorData> {
+        self.read_resource_handler(&request.uri, context)
+            .await
+            .map_err(|err| {
+                tracing::error!("{err}");
+                Self::resource_error(err)
+            })
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, rmcp::ErrorData> {
+        Ok(Self::list_resource_templates_handler())
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn subscribe(
+        &self,
+        request: SubscribeRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), rmcp::ErrorData> {
+        self.subscribe_handler(request.uri, context)
+            .await
+            .map_err(|err| {
+                tracing::error!("{err}");
+                Self::resource_error(err)
+            })
+    }
+
+    #[tracing::instrument(skip_all, parent = self.log_sink.span())]
+    async fn unsubscribe(
+        &self,
+        request: UnsubscribeRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), rmcp::ErrorData> {
+        if let Some(pdf_watcher) = &*self
+            .pdf_watcher
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            pdf_watcher.unsubscribe(&request.uri);
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,543 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use std::time::Duration;
+
+use eyre::{Result, eyre};
+use indexmap::IndexSet;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rmcp::model::ResourceUpdatedNotificationParam;
+use rmcp::{Peer, RoleServer};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use crate::cache::PdfCache;
+use crate::workspace::{PdfListCache, has_pdf_extension};
+
+// Builds and editors often write a file in several steps
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+// A recursive watch takes one inotify watch per subdirectory on Linux, so every session watching
+// the same root directory shares it
+static ROOT_WATCHER: LazyLock<RootWatcher> = LazyLock::new(RootWatcher::default);
+static NEXT_WATCHER_ID: AtomicU64 = AtomicU64::new(1);
+
+type EventSender = mpsc::UnboundedSender<notify::Result<Event>>;
+
+/// Watches subscribed PDFs and the root directories, and notifies the client when they change.
+///
+/// Changed files are also evicted from the cache, whether or not the client subscribed to them.
+pub struct PdfWatcher {
+    id: u64,
+    // Only watches the directories of subscribed PDFs, while root directories use `ROOT_WATCHER`
+    watcher: Mutex<RecommendedWatcher>,
+    tx: EventSender,
+    state: Arc<Mutex<WatchState>>,
+}
+
+/// Forwards the events under each root directory to the sessions that watch it.
+#[derive(Default)]
+struct RootWatcher {
+    // Created on first use
+    watcher: Mutex<Option<RecommendedWatcher>>,
+    // Root directory → ID of each `PdfWatcher` watching it → its event channel. Not locked while
+    // calling the watcher, which may wait for the thread that delivers events.
+    listeners: Arc<Mutex<HashMap<PathBuf, HashMap<u64, EventSender>>>>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    // Canonical path → URIs that the client subscribed with
+    subscriptions: HashMap<PathBuf, HashSet<String>>,
+    // Parent directory of subscribed files → number of subscribed files inside
+    watched_dirs: HashMap<PathBuf, usize>,
+    watched_roots: IndexSet<PathBuf>,
+    is_watching_roots: bool,
+}
+
+impl RootWatcher {
+    fn watch(&self, root: &Path, id: u64, tx: EventSender) -> Result<()> {
+        let mut watcher = self.watcher.lock().unwrap_or_else(PoisonError::into_inner);
+        let is_watched = self
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(root);
+        if !is_watched {
+            if watcher.is_none() {
+                let listeners = self.listeners.clone();
+                *watcher = Some(notify::recommended_watcher(
+                    move |event: notify::Result<Event>| Self::dispatch(&listeners, event),
+                )?);
+            }
+            if let Some(watcher) = &mut *watcher {
+                watcher.watch(root, RecursiveMode::Recursive)?;
+                tracing::info!("Watching root directory: {root:?}");
+            }
+        }
+        self.listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(root.to_owned())
+            .or_default()
+            .insert(id, tx);
+        Ok(())
+    }
+
+    fn unwatch(&self, root: &Path, id: u64) {
+        let mut watcher = self.watcher.lock().unwrap_or_else(PoisonError::into_inner);
+        {
+            let mut listeners = self
+                .listeners
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(sessions) = listeners.get_mut(root) else {
+                return;
+            };
+            sessions.remove(&id);
+            if !sessions.is_empty() {
+                return;
+            }
+            listeners.remove(root);
+        }
+        let Some(watcher) = &mut *watcher else {
+            return;
+        };
+        if let Err(err) = watcher.unwatch(root) {
+            tracing::debug!("Failed to stop watching root directory: {root:?} → {err}");
+        } else {
+            tracing::info!("Stopped watching root directory: {root:?}");
+        }
+    }
+
+    fn dispatch(
+        listeners: &Mutex<HashMap<PathBuf, HashMap<u64, EventSender>>>,
+        event: notify::Result<Event>,
+    ) {
+        let event = match event {
+            Ok(event) => event,
+            // Not attributed to any session
+            Err(err) => {
+                tracing::warn!("File watcher error: {err}");
+                return;
+            }
+        };
+        let listeners = listeners.lock().unwrap_or_else(PoisonError::into_inner);
+        for (root, sessions) in listeners.iter() {
+            if event.paths.iter().any(|path| path.starts_with(root)) {
+                for tx in sessions.values() {
+                    // Only fails after the session has ended
+                    let _ = tx.send(Ok(event.clone()));
+                }
+            }
+        }
+    }
+}
+
+impl PdfWatcher {
+    pub fn new(
+        peer: Peer<RoleServer>,
+        pdf_cache: Arc<PdfCache>,
+        pdf_list_cache: Arc<PdfListCache>,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let watcher = notify::recommended_watcher({
+            let tx = tx.clone();
+            move |event: notify::Result<Event>| {
+                // Only fails after the session has ended
+                let _ = tx.send(event);
+            }
+        })?;
+        let state = Arc::new(Mutex::new(WatchState::default()));
+        // Keep the span, so the logs go to the client of this session
+        tokio::spawn(
+            Self::run(rx, state.clone(), peer, pdf_cache, pdf_list_cache).in_current_span(),
+        );
+        Ok(Self {
+            id: NEXT_WATCHER_ID.fetch_add(1, Ordering::Relaxed),
+            watcher: Mutex::new(watcher),
+            tx,
+            state,
+        })
+    }
+
+    /// Starts watching a file, given its canonical path.
+    ///
+    /// The parent directory is watched instead of the file itself, because many programs replace
+    /// a file by renaming a new one over it.
+    pub fn subscribe(&self, path: PathBuf, uri: String) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(uris) = state.subscriptions.get_mut(&path) {
+            uris.insert(uri);
+            return Ok(());
+        }
+
+        let dir = path
+            .parent()
+            .ok_or_else(|| eyre!("Cannot watch a file without a parent directory: {path:?}"))?
+            .to_owned();
+        let count = state.watched_dirs.get(&dir).copied().unwrap_or_default();
+        if count == 0 {
+            self.watcher
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .watch(&dir, RecursiveMode::NonRecursive)?;
+            tracing::info!("Watching directory: {dir:?}");
+        }
+        state.watched_dirs.insert(dir, count + 1);
+        state.subscriptions.insert(path, HashSet::from([uri]));
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, uri: &str) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(path) = state
+            .subscriptions
+            .iter_mut()
+            .find_map(|(path, uris)| uris.remove(uri).then(|| path.clone()))
+        else {
+            return;
+        };
+        if !state.subscriptions[&path].is_empty() {
+            return;
+        }
+        state.subscriptions.remove(&path);
+
+        let Some(dir) = path.parent() else {
+            return;
+        };
+        let Some(count) = state.watched_dirs.get_mut(dir) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            state.watched_dirs.remove(dir);
+            if let Err(err) = self
+                .watcher
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .unwatch(dir)
+            {
+                tracing::debug!("Failed to stop watching directory: {dir:?} → {err}");
+            } else {
+                tracing::info!("Stopped watching directory: {dir:?}");
+            }
+        }
+    }
+
+    /// Watches the root directories recursively, so the client is told when PDFs are added or
+    /// removed. Root directories that are no longer in `roots` are not watched anymore.
+    ///
+    /// The watches are shared with other sessions watching the same root directories.
+    pub fn watch_roots(&self, roots: &IndexSet<PathBuf>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.is_watching_roots = true;
+        if state.watched_roots == *roots {
+            return;
+        }
+        for root in state.watched_roots.difference(roots) {
+            ROOT_WATCHER.unwatch(root, self.id);
+        }
+        let mut watched_roots = IndexSet::with_capacity(roots.len());
+        for root in roots {
+            if state.watched_roots.contains(root) {
+                watched_roots.insert(root.clone());
+                continue;
+            }
+            match ROOT_WATCHER.watch(root, self.id, self.tx.clone()) {
+                Ok(()) => {
+                    watched_roots.insert(root.clone());
+                }
+                Err(err) => tracing::warn!("Failed to watch root directory: {root:?} → {err}"),
+            }
+        }
+        state.watched_roots = watched_roots;
+    }
+
+    /// Whether `watch_roots` was called, so it should be called again when the roots change.
+    pub fn is_watching_roots(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_watching_roots
+    }
+
+    async fn run(
+        mut rx: mpsc::UnboundedReceiver<notify::Result<Event>>,
+        state: Arc<Mutex<WatchState>>,
+        peer: Peer<RoleServer>,
+        pdf_cache: Arc<PdfCache>,
+        pdf_list_cache: Arc<PdfListCache>,
+    ) {
+        while let Some(events) = Self::receive_batch(&mut rx).await {
+            let (changed_paths, added_or_removed_pdfs) = Self::collect_changes(events);
+            let (uris, list_changed) = state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .affected_by(&changed_paths, &added_or_removed_pdfs);
+            for path in &changed_paths {
+                pdf_cache.invalidate(path);
+            }
+            if !added_or_removed_pdfs.is_empty() {
+                pdf_list_cache.invalidate();
+            }
+
+            for uri in uris {
+                tracing::info!("Resource updated: {uri:?}");
+                if let Err(err) = peer
+                    .notify_resource_updated(ResourceUpdatedNotificationParam { uri })
+                    .await
+                {
+                    tracing::debug!("Failed to send resource update notification: {err}");
+                }
+            }
+            if list_changed {
+                tracing::info!("Resource list changed");
+                if let Err(err) = peer.notify_resource_list_changed().await {
+                    tracing::debug!("Failed to send resource list change notification: {err}");
+                }
+            }
+        }
+    }
+
+    /// Waits for an event, and then for more until none arrives within `DEBOUNCE_DELAY`.
+    async fn receive_batch(
+        rx: &mut mpsc::UnboundedReceiver<notify::Result<Event>>,
+    ) -> Option<Vec<notify::Result<Event>>> {
+        let first = rx.recv().await?;
+        let mut events = vec![first];
+        while let Ok(Some(event)) = tokio::time::timeout(DEBOUNCE_DELAY, rx.recv()).await {
+            events.push(event);
+        }
+        Some(events)
+    }
+
+    /// Returns the changed paths, and the PDFs that may have been added or removed.
+    fn collect_changes(events: Vec<notify::Result<Event>>) -> (HashSet<PathBuf>, Vec<PathBuf>) {
+        let mut changed_paths = HashSet::new();
+        let mut added_or_removed_pdfs = Vec::new();
+        for event in events {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    tracing::warn!("File watcher error: {err}");
+                    continue;
+                }
+            };
+            let added_or_removed = match event.kind {
+                EventKind::Access(_) => continue,
+                EventKind::Create(_)
+                | EventKind::Remove(_)
+                | EventKind::Modify(ModifyKind::Name(_))
+                | EventKind::Any => true,
+                _ => false,
+            };
+            for path in event.paths {
+                if added_or_removed && has_pdf_extension(&path) {
+                    added_or_removed_pdfs.push(path.clone());
+                }
+                changed_paths.insert(path);
+            }
+        }
+        (changed_paths, added_or_removed_pdfs)
+    }
+}
+
+impl WatchState {
+    /// Returns the subscribed URIs that changed, and whether the resource list changed.
+    fn affected_by(
+        &self,
+        changed_paths: &HashSet<PathBuf>,
+        added_or_removed_pdfs: &[PathBuf],
+    ) -> (Vec<String>, bool) {
+        let uris = changed_paths
+            .iter()
+            .filter_map(|path| self.subscriptions.get(path))
+            .flatten()
+            .cloned()
+            .collect();
+        let list_changed = added_or_removed_pdfs
+            .iter()
+            .any(|path| self.watched_roots.iter().any(|root| path.starts_with(root)));
+        (uris, list_changed)
+    }
+}
+
+impl Drop for PdfWatcher {
+    fn drop(&mut self) {
+        // Otherwise the shared watcher keeps the event channel, and `run`, alive
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for root in &state.watched_roots {
+            ROOT_WATCHER.unwatch(root, self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{AccessKind, CreateKind, DataChange, RemoveKind};
+
+    use super::*;
+
+    fn modified() -> EventKind {
+        EventKind::Modify(ModifyKind::Data(DataChange::Content))
+    }
+
+    fn event(kind: EventKind, path: &Path) -> notify::Result<Event> {
+        Ok(Event::new(kind).add_path(path.to_owned()))
+    }
+
+    fn event_paths(events: Vec<notify::Result<Event>>) -> Vec<PathBuf> {
+        events
+            .into_iter()
+            .flat_map(|event| event.unwrap().paths)
+            .collect()
+    }
+
+    // Waits until an event for `path` arrives
+    async fn receive_event(rx: &mut mpsc::UnboundedReceiver<notify::Result<Event>>, path: &Path) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = rx.recv().await {
+                if event
+                    .unwrap()
+                    .paths
+                    .iter()
+                    .any(|event_path| event_path == path)
+                {
+                    return;
+                }
+            }
+            panic!("Channel closed before an event for {path:?}");
+        })
+        .await
+        .unwrap();
+    }
+
+    // Returns the paths of the events left, once the channel is closed
+    async fn remaining_paths(
+        mut rx: mpsc::UnboundedReceiver<notify::Result<Event>>,
+    ) -> Vec<PathBuf> {
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        event_paths(events)
+    }
+
+    #[tokio::test]
+    async fn shares_root_watches_between_sessions() {
+        let root =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        // Some platforms report the canonical path
+        let root = root.canonicalize().unwrap();
+        let watcher = RootWatcher::default();
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let (tx2, mut rx2) = mpsc::unbounded_channel();
+        watcher.watch(&root, 1, tx1).unwrap();
+        watcher.watch(&root, 2, tx2).unwrap();
+
+        let first = root.join("first.pdf");
+        std::fs::write(&first, "").unwrap();
+        receive_event(&mut rx1, &first).await;
+        receive_event(&mut rx2, &first).await;
+
+        // The other session still gets events
+        watcher.unwatch(&root, 1);
+        assert!(watcher.listeners.lock().unwrap().contains_key(&root));
+        let second = root.join("second.pdf");
+        std::fs::write(&second, "").unwrap();
+        receive_event(&mut rx2, &second).await;
+        assert!(!remaining_paths(rx1).await.contains(&second));
+
+        // The last session stops watching the root directory
+        watcher.unwatch(&root, 2);
+        assert!(watcher.listeners.lock().unwrap().is_empty());
+        let third = root.join("third.pdf");
+        std::fs::write(&third, "").unwrap();
+        assert!(!remaining_paths(rx2).await.contains(&third));
+
+        // Unknown sessions are ignored
+        watcher.unwatch(&root, 3);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn debounces_events() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tx.send(event(modified(), Path::new("a.pdf"))).unwrap();
+        tx.send(event(modified(), Path::new("b.pdf"))).unwrap();
+        let sender = tokio::spawn(async move {
+            tokio::time::sleep(DEBOUNCE_DELAY / 4).await;
+            tx.send(event(modified(), Path::new("c.pdf"))).unwrap();
+            tokio::time::sleep(DEBOUNCE_DELAY * 3).await;
+            tx.send(event(modified(), Path::new("d.pdf"))).unwrap();
+        });
+
+        let batch = PdfWatcher::receive_batch(&mut rx).await.unwrap();
+        assert_eq!(
+            event_paths(batch),
+            [Path::new("a.pdf"), Path::new("b.pdf"), Path::new("c.pdf")]
+        );
+        let batch = PdfWatcher::receive_batch(&mut rx).await.unwrap();
+        assert_eq!(event_paths(batch), [Path::new("d.pdf")]);
+        sender.await.unwrap();
+        assert!(PdfWatcher::receive_batch(&mut rx).await.is_none());
+    }
+
+    #[test]
+    fn notifies_added_removed_and_subscribed_pdfs() {
+        let root = Path::new("/root");
+        let subscribed = root.join("subscribed.pdf");
+        let state = WatchState {
+            subscriptions: HashMap::from([(
+                subscribed.clone(),
+                HashSet::from(["file:///root/subscribed.pdf".to_owned()]),
+            )]),
+            watched_roots: IndexSet::from([root.to_owned()]),
+            ..WatchState::default()
+        };
+        let (changed_paths, added_or_removed_pdfs) = PdfWatcher::collect_changes(vec![
+            event(EventKind::Create(CreateKind::File), &root.join("added.pdf")),
+            event(
+                EventKind::Remove(RemoveKind::File),
+                &root.join("removed.PDF"),
+            ),
+            event(EventKind::Create(CreateKind::File), &root.join("notes.txt")),
+            event(EventKind::Access(AccessKind::Any), &root.join("read.pdf")),
+            event(modified(), &subscribed),
+            Err(notify::Error::generic("failed")),
+        ]);
+        assert_eq!(
+            changed_paths,
+            HashSet::from([
+                root.join("added.pdf"),
+                root.join("removed.PDF"),
+                root.join("notes.txt"),
+                subscribed.clone(),
+            ])
+        );
+        assert_eq!(
+            added_or_removed_pdfs,
+            [root.join("added.pdf"), root.join("removed.PDF")]
+        );
+        let (uris, list_changed) = state.affected_by(&changed_paths, &added_or_removed_pdfs);
+        assert_eq!(uris, ["file:///root/subscribed.pdf"]);
+        assert!(list_changed);
+
+        // Modified PDFs and PDFs outside the root directories don't change the list
+        let (changed_paths, added_or_removed_pdfs) = PdfWatcher::collect_changes(vec![
+            event(modified(), &root.join("other.pdf")),
+            event(
+                EventKind::Create(CreateKind::File),
+                Path::new("/elsewhere/added.pdf"),
+            ),
+        ]);
+        let (uris, list_changed) = state.affected_by(&changed_paths, &added_or_removed_pdfs);
+        assert!(uris.is_empty());
+        assert!(!list_changed);
+    }
+}
@@ -0,0 +1,629 @@
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::Duration;
+
+use base64::prelude::*;
+use eyre::{Result, bail, eyre};
+use hayro::hayro_syntax::Pdf;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Semaphore, oneshot};
+use tokio_util::sync::CancellationToken;
+
+use crate::cache::LoadedPdf;
+use crate::search::extract_page_text;
+use crate::service::PdflensService;
+
+/// Runs pdf-extract, and optionally the renderer, in worker processes, so that a crash on a
+/// malformed PDF only fails one request instead of ending every session.
+///
+/// A worker that crashed is replaced by a new one on the next request. Nothing is isolated
+/// unless `WorkerConfig` says so.
+pub struct PdfWorkers {
+    config: WorkerConfig,
+    idle: Mutex<Vec<WorkerProcess>>,
+    // Bounds the number of worker processes, as each one may use up to its memory limit
+    permits: Semaphore,
+}
+
+#[derive(Default)]
+pub struct WorkerConfig {
+    /// A program that calls `run_pdf_worker` when started with `args`, e.g. `pdflens-mcp worker`
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub isolate_text: bool,
+    pub isolate_rendering: bool,
+    /// Wall-clock time limit of each request, after which the worker is killed
+    pub timeout: Option<Duration>,
+}
+
+/// Resource limits of a worker process, which it applies to itself. Zero means unlimited.
+#[derive(Clone, Copy, Debug, clap::Args)]
+pub struct WorkerLimits {
+    /// Address space limit in MiB
+    #[arg(long, default_value_t = 0)]
+    pub max_memory_mib: u64,
+    /// CPU time limit for each page in seconds
+    #[arg(long, default_value_t = 0)]
+    pub max_cpu_secs: u64,
+}
+
+struct WorkerProcess {
+    // Shared with the watchdog, which kills the worker on cancellation or timeout
+    child: Arc<Mutex<Child>>,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    // The file data of the PDF that the worker has parsed
+    loaded: Weak<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum WorkerRequest {
+    Load {
+        // Base64
+        data: String,
+        password: Option<String>,
+    },
+    ExtractText {
+        page_idx: usize,
+    },
+    RenderPage {
+        page_idx: usize,
+        image_dimension: u16,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum WorkerResponse {
+    Loaded,
+    Text(String),
+    // Base64
+    Png(String),
+    Error(String),
+}
+
+impl Default for PdfWorkers {
+    fn default() -> Self {
+        Self::new(WorkerConfig::default())
+    }
+}
+
+impl PdfWorkers {
+    pub fn new(config: WorkerConfig) -> Self {
+        Self {
+            config,
+            idle: Mutex::default(),
+            permits: Semaphore::new(max_workers()),
+        }
+    }
+
+    /// Extracts the text of one page, given its 0-based index. Blocks the current thread, which
+    /// must be inside a Tokio runtime, e.g. with `spawn_blocking`.
+    pub fn extract_page_text(
+        &self,
+        pdf: &LoadedPdf,
+        page_idx: usize,
+        ct: &CancellationToken,
+    ) -> Result<String> {
+        if !self.config.isolate_text {
+            return extract_page_text(pdf, page_idx);
+        }
+        match self.call(pdf, page_idx, &WorkerRequest::ExtractText { page_idx }, ct)? {
+            WorkerResponse::Text(text) => Ok(text),
+            response => Err(Self::unexpected_response(response)),
+        }
+    }
+
+    /// Renders one page to PNG, given its 0-based index. Blocks the current thread, which must be
+    /// inside a Tokio runtime, e.g. with `spawn_blocking`.
+    pub fn render_page_png(
+        &self,
+        pdf: &LoadedPdf,
+        page_idx: usize,
+        image_dimension: u16,
+        ct: &CancellationToken,
+    ) -> Result<Vec<u8>> {
+        if !self.config.isolate_rendering {
+            return PdflensService::render_page_png(&pdf.pages()[page_idx], image_dimension);
+        }
+        let request = WorkerRequest::RenderPage {
+            page_idx,
+            image_dimension,
+        };
+        match self.call(pdf, page_idx, &request, ct)? {
+            WorkerResponse::Png(image) => Ok(BASE64_STANDARD.decode(image)?),
+            response => Err(Self::unexpected_response(response)),
+        }
+    }
+
+    fn call(
+        &self,
+        pdf: &LoadedPdf,
+        page_idx: usize,
+        request: &WorkerRequest,
+        ct: &CancellationToken,
+    ) -> Result<WorkerResponse> {
+        // A new worker is only started when none is idle, so this also bounds the idle ones
+        let _permit = tokio::runtime::Handle::current().block_on(async {
+            tokio::select! {
+                permit = self.permits.acquire() => Ok(permit?),
+                () = ct.cancelled() => Err(eyre!("Cancelled")),
+            }
+        })?;
+        let idle_worker = self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let mut worker = match idle_worker {
+            Some(worker) => worker,
+            None => WorkerProcess::spawn(&self.config)?,
+        };
+
+        let (done_tx, watchdog) = self.watchdog(&worker, ct);
+        let result = (|| {
+            if !Weak::ptr_eq(&worker.loaded, &Arc::downgrade(&pdf.data)) {
+                // The worker unloads its PDF even if the new one fails to load
+                worker.loaded = Weak::new();
+                let load = WorkerRequest::Load {
+                    data: BASE64_STANDARD.encode(&*pdf.data),
+                    password: pdf.password.clone(),
+                };
+                match worker.call(&load)? {
+                    WorkerResponse::Loaded => worker.loaded = Arc::downgrade(&pdf.data),
+                    response => return Ok(response),
+                }
+            }
+            worker.call(request)
+        })();
+        drop(done_tx);
+        let stop_reason = tokio::runtime::Handle::current()
+            .block_on(watchdog)
+            .ok()
+            .flatten();
+
+        match stop_reason {
+            Some(StopReason::Cancelled) => bail!("Cancelled"),
+            Some(StopReason::TimedOut(timeout)) => {
+                let status = worker.terminate();
+                tracing::warn!(
+                    "PDF worker timed out on page {} after {timeout:?}. Exit status: {status}",
+                    page_idx + 1
+                );
+                bail!(
+                    "Failed to process page {} of the PDF, because it took longer than {} seconds.\nThe page may be malformed. Other pages can still be read.",
+                    page_idx + 1,
+                    timeout.as_secs()
+                )
+            }
+            None => {}
+        }
+        match result {
+            Ok(WorkerResponse::Error(err)) => {
+                self.release(worker);
+                bail!(err)
+            }
+            Ok(response) => {
+                self.release(worker);
+                Ok(response)
+            }
+            Err(err) => {
+                // The worker crashed, or was killed by a resource limit
+                let status = worker.terminate();
+                tracing::warn!(
+                    "PDF worker failed on page {}: {err}. Exit status: {status}",
+                    page_idx + 1
+                );
+                bail!(
+                    "Failed to process page {} of the PDF, because it crashed the PDF reader ({status}).\nThe page may be malformed. Other pages can still be read.",
+                    page_idx + 1
+                )
+            }
+        }
+    }
+
+    /// Kills the worker when the request is cancelled or times out, unless the returned sender
+    /// is dropped first. A worker that hangs without using CPU time would otherwise never stop.
+    fn watchdog(
+        &self,
+        worker: &WorkerProcess,
+        ct: &CancellationToken,
+    ) -> (
+        oneshot::Sender<()>,
+        tokio::task::JoinHandle<Option<StopReason>>,
+    ) {
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        let child = worker.child.clone();
+        let ct = ct.clone();
+        let timeout = self.config.timeout;
+        let watchdog = tokio::runtime::Handle::current().spawn(async move {
+            let stop_reason = tokio::select! {
+                _ = done_rx => return None,
+                () = ct.cancelled() => StopReason::Cancelled,
+                () = async {
+                    match timeout {
+                        Some(timeout) => tokio::time::sleep(timeout).await,
+                        None => std::future::pending().await,
+                    }
+                } => StopReason::TimedOut(timeout.unwrap_or_default()),
+            };
+            let _ = child.lock().unwrap_or_else(PoisonError::into_inner).kill();
+            Some(stop_reason)
+        });
+        (done_tx, watchdog)
+    }
+
+    fn release(&self, worker: WorkerProcess) {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        // The rest exit when dropped
+        if idle.len() < max_workers() {
+            idle.push(worker);
+        }
+    }
+
+    fn unexpected_response(response: WorkerResponse) -> eyre::Report {
+        match response {
+            WorkerResponse::Error(err) => eyre!(err),
+            _ => eyre!("Unexpected response from the PDF worker"),
+        }
+    }
+}
+
+/// As many workers as there are CPUs
+fn max_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, usize::from)
+}
+
+enum StopReason {
+    Cancelled,
+    TimedOut(Duration),
+}
+
+impl WorkerProcess {
+    fn spawn(config: &WorkerConfig) -> Result<Self> {
+        let mut child = Command::new(&config.program)
+            .args(&config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| eyre!("Failed to start PDF worker {:?}: {err}", config.program))?;
+        tracing::debug!("Started PDF worker: {}", child.id());
+        let stdin = child.stdin.take().ok_or_else(|| eyre!("No stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| eyre!("No stdout"))?;
+        Ok(Self {
+            child: Arc::new(Mutex::new(child)),
+            stdin: BufWriter::new(stdin),
+            stdout: BufReader::new(stdout),
+            loaded: Weak::new(),
+        })
+    }
+
+    fn call(&mut self, request: &WorkerRequest) -> std::io::Result<WorkerResponse> {
+        serde_json::to_writer(&mut self.stdin, request)?;
+        self.stdin.write_all(b"\n")?;
+        self.stdin.flush()?;
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            // Only on platforms where `run_pdf_worker` cannot keep pdf-extract off the protocol
+            match serde_json::from_str(&line) {
+                Ok(response) => return Ok(response),
+                Err(_) => tracing::debug!("Ignored output of PDF worker: {line:?}"),
+            }
+        }
+    }
+
+    fn terminate(&mut self) -> String {
+        let mut child = self.child.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = child.kill();
+        match child.wait() {
+            Ok(status) => status.to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
+
+/// Serves requests from `PdfWorkers` on stdin and stdout, until stdin is closed.
+pub fn run_pdf_worker(limits: WorkerLimits) -> Result<()> {
+    limit_memory(limits.max_memory_mib);
+
+    let mut stdout = BufWriter::new(take_stdout()?);
+    let mut pdf = None::<Pdf>;
+    for line in std::io::stdin().lock().lines() {
+        let request = serde_json::from_str::<WorkerRequest>(&line?)?;
+        let response = match request {
+            WorkerRequest::Load { data, password } => {
+                pdf = None;
+                match BASE64_STANDARD
+                    .decode(data)
+                    .map_err(eyre::Report::from)
+                    .and_then(|data| PdflensService::parse_pdf(Arc::new(data), password.as_deref()))
+                {
+                    Ok((new_pdf, _)) => {
+                        pdf = Some(new_pdf);
+                        WorkerResponse::Loaded
+                    }
+                    Err(err) => WorkerResponse::Error(format!("{err:#}")),
+                }
+            }
+            WorkerRequest::ExtractText { page_idx } => {
+                limit_cpu(limits.max_cpu_secs);
+                catch_panic(|| {
+                    let pdf = pdf.as_ref().ok_or_else(|| eyre!("No PDF is loaded"))?;
+                    Ok(WorkerResponse::Text(extract_page_text(pdf, page_idx)?))
+                })
+            }
+            WorkerRequest::RenderPage {
+                page_idx,
+                image_dimension,
+            } => {
+                limit_cpu(limits.max_cpu_secs);
+                catch_panic(|| {
+                    let pdf = pdf.as_ref().ok_or_else(|| eyre!("No PDF is loaded"))?;
+                    let page = pdf
+                        .pages()
+                        .get(page_idx)
+                        .ok_or_else(|| eyre!("Page index {page_idx} is out of range"))?;
+                    let image = PdflensService::render_page_png(page, image_dimension)?;
+                    Ok(WorkerResponse::Png(BASE64_STANDARD.encode(image)))
+                })
+            }
+        };
+        serde_json::to_writer(&mut stdout, &response)?;
+        stdout.write_all(b"\n")?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// pdf-extract may print to stdout by itself, which would corrupt the responses. So the
+/// responses go to a duplicate of stdout, while stdout itself is pointed at stderr.
+#[cfg(unix)]
+fn take_stdout() -> Result<std::fs::File> {
+    use std::os::fd::AsFd;
+
+    let protocol = std::io::stdout().as_fd().try_clone_to_owned()?;
+    nix::unistd::dup2_stdout(std::io::stderr())?;
+    Ok(protocol.into())
+}
+
+#[cfg(not(unix))]
+fn take_stdout() -> Result<std::io::Stdout> {
+    Ok(std::io::stdout())
+}
+
+/// A panic only fails the current request, as the parsed PDF is never modified.
+fn catch_panic(f: impl FnOnce() -> Result<WorkerResponse>) -> WorkerResponse {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => WorkerResponse::Error(format!("{err:#}")),
+        Err(_) => WorkerResponse::Error("The PDF reader panicked".to_owned()),
+    }
+}
+
+#[cfg(unix)]
+fn limit_memory(max_memory_mib: u64) {
+    use nix::sys::resource::{Resource, setrlimit};
+
+    if max_memory_mib == 0 {
+        return;
+    }
+    let max_memory = max_memory_mib.saturating_mul(1024 * 1024);
+    if let Err(err) = setrlimit(Resource::RLIMIT_AS, max_memory, max_memory) {
+        tracing::warn!("Failed to limit the memory of the PDF worker: {err}");
+    }
+}
+
+/// Allows the worker to use `max_cpu_secs` more seconds of CPU time, after which the kernel
+/// kills it. The limit counts the whole lifetime of the process, so it is raised per request.
+#[cfg(unix)]
+fn limit_cpu(max_cpu_secs: u64) {
+    use nix::sys::resource::{Resource, UsageWho, getrlimit, getrusage, setrlimit};
+
+    if max_cpu_secs == 0 {
+        return;
+    }
+    let result = getrusage(UsageWho::RUSAGE_SELF).and_then(|usage| {
+        let used_secs = (usage.user_time().tv_sec() + usage.system_time().tv_sec()) as u64;
+        let (_, hard_limit) = getrlimit(Resource::RLIMIT_CPU)?;
+        let soft_limit = used_secs.saturating_add(max_cpu_secs).min(hard_limit);
+        setrlimit(Resource::RLIMIT_CPU, soft_limit, hard_limit)
+    });
+    if let Err(err) = result {
+        tracing::warn!("Failed to limit the CPU time of the PDF worker: {err}");
+    }
+}
+
+#[cfg(not(unix))]
+fn limit_memory(_max_memory_mib: u64) {}
+
+#[cfg(not(unix))]
+fn limit_cpu(_max_cpu_secs: u64) {}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::cache::tests::test_pdf;
+
+    // Starts `worker_main` as a real worker, from a shell script given to `workers`
+    const START_WORKER: &str = r#"PDFLENS_TEST_WORKER=1 exec "$0" "$@""#;
+
+    #[test]
+    #[ignore = "started by the other tests as a PDF worker"]
+    fn worker_main() {
+        if std::env::var_os("PDFLENS_TEST_WORKER").is_some() {
+            let limits = WorkerLimits {
+                max_memory_mib: 0,
+                max_cpu_secs: 0,
+            };
+            std::process::exit(i32::from(run_pdf_worker(limits).is_err()));
+        }
+    }
+
+    /// Runs each worker as a shell script, which may end with `START_WORKER`.
+    fn workers(script: &str, timeout: Option<Duration>) -> Arc<PdfWorkers> {
+        let mut args = vec![
+            "-c".to_owned(),
+            script.to_owned(),
+            std::env::current_exe()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned(),
+        ];
+        args.extend(
+            [
+                "worker::tests::worker_main",
+                "--exact",
+                "--ignored",
+                "--nocapture",
+            ]
+            .map(str::to_owned),
+        );
+        Arc::new(PdfWorkers::new(WorkerConfig {
+            program: PathBuf::from("/bin/sh"),
+            args,
+            isolate_text: true,
+            isolate_rendering: true,
+            timeout,
+        }))
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir(&path);
+        path
+    }
+
+    async fn extract(
+        workers: &Arc<PdfWorkers>,
+        pdf: &Arc<LoadedPdf>,
+        page_idx: usize,
+        ct: &CancellationToken,
+    ) -> Result<String> {
+        let (workers, pdf, ct) = (workers.clone(), pdf.clone(), ct.clone());
+        tokio::task::spawn_blocking(move || workers.extract_page_text(&pdf, page_idx, &ct))
+            .await
+            .unwrap()
+    }
+
+    fn idle_workers(workers: &PdfWorkers) -> usize {
+        workers.idle.lock().unwrap().len()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn round_trip() {
+        let workers = workers(START_WORKER, None);
+        let pdf = test_pdf(&["First page", "Second page"]);
+        let ct = CancellationToken::new();
+        let text = extract(&workers, &pdf, 0, &ct).await.unwrap();
+        assert!(text.contains("First page"), "{text:?}");
+        // The same worker serves the next page, without loading the PDF again
+        let text = extract(&workers, &pdf, 1, &ct).await.unwrap();
+        assert!(text.contains("Second page"), "{text:?}");
+        assert_eq!(idle_workers(&workers), 1);
+
+        let image = tokio::task::spawn_blocking({
+            let (workers, pdf, ct) = (workers.clone(), pdf.clone(), ct.clone());
+            move || workers.render_page_png(&pdf, 0, 64, &ct)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(image.starts_with(b"\x89PNG"));
+
+        // An error inside the worker fails the request, but the worker is kept
+        assert!(extract(&workers, &pdf, 5, &ct).await.is_err());
+        assert_eq!(idle_workers(&workers), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn restarts_after_crash() {
+        let marker = temp_path("crashed");
+        // The first worker exits on its first request, like after a crash
+        let workers = workers(
+            &format!(
+                r#"if mkdir {marker:?} 2>/dev/null; then read line; exit 1; fi; {START_WORKER}"#
+            ),
+            None,
+        );
+        let pdf = test_pdf(&["Only page"]);
+        let ct = CancellationToken::new();
+        let err = extract(&workers, &pdf, 0, &ct).await.unwrap_err();
+        assert!(err.to_string().contains("crashed"), "{err}");
+        assert_eq!(idle_workers(&workers), 0);
+
+        let text = extract(&workers, &pdf, 0, &ct).await.unwrap();
+        assert!(text.contains("Only page"), "{text:?}");
+        std::fs::remove_dir(&marker).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn kills_stuck_workers() {
+        let workers = workers("read line; exec sleep 60", Some(Duration::from_millis(200)));
+        let pdf = test_pdf(&["Only page"]);
+        let started = Instant::now();
+        let err = extract(&workers, &pdf, 0, &CancellationToken::new())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("took longer"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert_eq!(idle_workers(&workers), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancels_requests() {
+        let workers = workers("read line; exec sleep 60", None);
+        let pdf = test_pdf(&["Only page"]);
+        let ct = CancellationToken::new();
+        let task = tokio::spawn({
+            let (workers, pdf, ct) = (workers.clone(), pdf.clone(), ct.clone());
+            async move { extract(&workers, &pdf, 0, &ct).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        ct.cancel();
+        let err = task.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("Cancelled"), "{err}");
+        assert_eq!(idle_workers(&workers), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn waits_for_a_free_worker() {
+        let marker = temp_path("started");
+        let workers = workers(&format!("mkdir {marker:?}; {START_WORKER}"), None);
+        let pdf = test_pdf(&["Only page"]);
+        let permits = workers
+            .permits
+            .acquire_many(max_workers() as u32)
+            .await
+            .unwrap();
+
+        let task = tokio::spawn({
+            let (workers, pdf) = (workers.clone(), pdf.clone());
+            async move { extract(&workers, &pdf, 0, &CancellationToken::new()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!marker.exists());
+
+        drop(permits);
+        let text = task.await.unwrap().unwrap();
+        assert!(text.contains("Only page"), "{text:?}");
+        assert!(marker.exists());
+        std::fs::remove_dir(&marker).unwrap();
+    }
+}
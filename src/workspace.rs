@@ -0,0 +1,448 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use eyre::{Result, eyre};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use indexmap::IndexSet;
+use url::Url;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::policy::AccessPolicy;
+
+const MAX_WORKSPACE_PDFS: usize = 10000;
+const MAX_SUGGESTIONS: usize = 5;
+// The root directories are not always watched, so a listing may miss changes
+const PDF_LIST_CACHE_TTL: Duration = Duration::from_secs(10);
+
+pub struct WorkspacePdf {
+    pub path: PathBuf,
+    pub relative_path: PathBuf,
+}
+
+/// The PDFs in the root directories of a session, kept between completion requests, which come
+/// on every keystroke.
+///
+/// It is cleared when the watcher sees a PDF added or removed, and expires after a while anyway.
+#[derive(Default)]
+pub struct PdfListCache {
+    inner: Mutex<Option<CachedPdfList>>,
+}
+
+struct CachedPdfList {
+    roots: IndexSet<PathBuf>,
+    pdfs: Arc<Vec<WorkspacePdf>>,
+    listed_at: Instant,
+}
+
+impl PdfListCache {
+    pub fn get(&self, roots: &IndexSet<PathBuf>) -> Option<Arc<Vec<WorkspacePdf>>> {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner
+            .as_ref()
+            .filter(|list| list.roots == *roots && list.listed_at.elapsed() < PDF_LIST_CACHE_TTL)
+            .map(|list| list.pdfs.clone())
+    }
+
+    pub fn insert(&self, roots: IndexSet<PathBuf>, pdfs: Arc<Vec<WorkspacePdf>>) {
+        *self.inner.lock().unwrap_or_else(PoisonError::into_inner) = Some(CachedPdfList {
+            roots,
+            pdfs,
+            listed_at: Instant::now(),
+        });
+    }
+
+    pub fn invalidate(&self) {
+        *self.inner.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+/// Include and exclude globs, matched against paths relative to the root directory.
+#[derive(Default)]
+pub struct PdfFilter {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+}
+
+impl PdfFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: Self::build_glob_set(include)?,
+            exclude: Self::build_glob_set(exclude)?,
+        })
+    }
+
+    fn build_glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(
+                GlobBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|err| eyre!("Invalid glob pattern: {pattern:?} → {err}"))?,
+            );
+        }
+        Ok(Some(builder.build()?))
+    }
+
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative_path))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(relative_path))
+    }
+}
+
+/// Walks the root directories for PDFs, skipping hidden directories and denied paths.
+///
+/// Symbolic links are only followed if the access policy allows them to point outside the root
+/// directories. Otherwise, every PDF found stays inside its root directory.
+pub fn find_pdfs(
+    roots: &IndexSet<PathBuf>,
+    filter: &PdfFilter,
+    max_depth: Option<usize>,
+    access_policy: &AccessPolicy,
+) -> Vec<WorkspacePdf> {
+    let mut seen = HashSet::new();
+    let mut pdfs = Vec::new();
+    for root in roots {
+        let mut walker = WalkDir::new(root)
+            .follow_links(access_policy.allow_symlink_escape)
+            .sort_by_file_name();
+        if let Some(max_depth) = max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+        for entry in walker.into_iter().filter_entry(|entry| {
+            entry.depth() == 0 || !(is_hidden(entry) || access_policy.is_denied(entry.path(), root))
+        }) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    tracing::debug!("Skipped unreadable path: {err}");
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() || !has_pdf_extension(entry.path()) {
+                continue;
+            }
+            let Ok(relative_path) = entry.path().strip_prefix(root) else {
+                continue;
+            };
+            // Nested root directories would otherwise list the same PDF twice
+            if !filter.matches(relative_path) || !seen.insert(entry.path().to_owned()) {
+                continue;
+            }
+            pdfs.push(WorkspacePdf {
+                path: entry.path().to_owned(),
+                relative_path: relative_path.to_owned(),
+            });
+            if pdfs.len() >= MAX_WORKSPACE_PDFS {
+                tracing::warn!(
+                    "Found more than {MAX_WORKSPACE_PDFS} PDFs in the workspace, ignoring the rest"
+                );
+                return pdfs;
+            }
+        }
+    }
+    pdfs
+}
+
+/// Suggests the relative paths of PDFs for a partially typed `path` argument, best matches first.
+///
+/// Paths starting with the typed value come first, then file names starting with it, then file
+/// names and paths containing its characters in order, so “spec” also finds “Specification.pdf”.
+///
+/// A relative path that exists in more than one root directory would be rejected as ambiguous,
+/// so those PDFs are suggested as absolute URIs instead.
+pub fn complete_pdf_path(pdfs: &[WorkspacePdf], value: &str) -> Vec<String> {
+    let value = value.trim_start_matches("./").to_lowercase();
+    let mut relative_path_counts = HashMap::<&Path, usize>::new();
+    for pdf in pdfs {
+        *relative_path_counts.entry(&pdf.relative_path).or_default() += 1;
+    }
+    let mut candidates = pdfs
+        .iter()
+        .filter_map(|pdf| {
+            let relative_path = pdf.relative_path.to_string_lossy();
+            let lower_path = relative_path.to_lowercase();
+            let lower_name = pdf
+                .relative_path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let rank = if lower_path.starts_with(&value) {
+                0
+            } else if lower_name.starts_with(&value) {
+                1
+            } else if is_subsequence(&value, &lower_name) {
+                2
+            } else if is_subsequence(&value, &lower_path) {
+                3
+            } else {
+                return None;
+            };
+            if relative_path_counts[pdf.relative_path.as_path()] > 1 {
+                return Some((rank, path_to_uri(&pdf.path).into_owned()));
+            }
+            Some((rank, relative_path.into_owned()))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|(a_rank, a_path), (b_rank, b_path)| {
+        a_rank
+            .cmp(b_rank)
+            .then(a_path.len().cmp(&b_path.len()))
+            .then(a_path.cmp(b_path))
+    });
+    candidates.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Finds the PDFs that were probably meant by a path that doesn’t exist, best matches first.
+///
+/// `relative_path` should be relative to the root directories if possible. Otherwise, only the
+/// file name is compared.
+pub fn suggest_pdf_paths<'a>(
+    pdfs: &'a [WorkspacePdf],
+    relative_path: &Path,
+) -> Vec<&'a WorkspacePdf> {
+    let lowercase = |path: &Path| path.to_string_lossy().to_lowercase();
+    let file_stem = |path: &Path| path.file_stem().map(lowercase).unwrap_or_default();
+    let relative_path = relative_path.strip_prefix(".").unwrap_or(relative_path);
+    let target_path = lowercase(relative_path);
+    let target_path_without_extension = lowercase(&relative_path.with_extension(""));
+    let target_stem = file_stem(relative_path);
+    // Allow roughly one typo every four characters
+    let max_distance = (target_stem.chars().count() / 4).max(1);
+
+    let mut suggestions = pdfs
+        .iter()
+        .filter_map(|pdf| {
+            let stem = file_stem(&pdf.relative_path);
+            let rank = if lowercase(&pdf.relative_path) == target_path {
+                // Only the letter case is different
+                (0, 0)
+            } else if lowercase(&pdf.relative_path.with_extension(""))
+                == target_path_without_extension
+            {
+                // The extension is wrong or missing
+                (1, 0)
+            } else if stem == target_stem {
+                // The same file name in another directory
+                (2, 0)
+            } else {
+                let distance = edit_distance(&stem, &target_stem);
+                if distance > max_distance {
+                    return None;
+                }
+                (3, distance)
+            };
+            Some((rank, pdf))
+        })
+        .collect::<Vec<_>>();
+    suggestions.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank
+            .cmp(b_rank)
+            .then_with(|| a.relative_path.cmp(&b.relative_path))
+    });
+    suggestions
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, pdf)| pdf)
+        .collect()
+}
+
+/// Levenshtein distance, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev_row = (0..=b.len()).collect::<Vec<_>>();
+    let mut row = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        row[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = prev_row[j] + usize::from(a_char != b_char);
+            row[j + 1] = substitution.min(prev_row[j + 1] + 1).min(row[j] + 1);
+        }
+        std::mem::swap(&mut prev_row, &mut row);
+    }
+    prev_row[b.len()]
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+pub fn has_pdf_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+}
+
+/// Resolves `.` and `..` components without touching the file system. Unlike
+/// `std::fs::canonicalize`, symbolic links are kept as is.
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Resolves symbolic links and `..` components one at a time, as the OS does, and returns the
+/// real path if it stays inside `root`, which should be a canonical path.
+///
+/// If `allow_symlink_escape` is set, the path may also leave the root through a symbolic link
+/// inside it, like `find_pdfs` does when following links. It may not climb out of the link
+/// target with `..`, so `root/link/../secret.pdf` is only accepted if its real path is inside.
+pub fn resolve_in_root(
+    path: &Path,
+    root: &Path,
+    allow_symlink_escape: bool,
+) -> std::io::Result<Option<PathBuf>> {
+    let mut resolved = PathBuf::new();
+    // The target of the symbolic link through which the path left the root
+    let mut escape_base: Option<PathBuf> = None;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                // Climbing out of the link target ends the escape, so the rest must stay inside
+                if escape_base.as_ref() == Some(&resolved) {
+                    escape_base = None;
+                }
+                resolved.pop();
+            }
+            Component::Normal(name) => {
+                let candidate = resolved.join(name);
+                if !std::fs::symlink_metadata(&candidate)?.is_symlink() {
+                    resolved = candidate;
+                    continue;
+                }
+                let target = std::fs::canonicalize(&candidate)?;
+                if target.starts_with(root) {
+                    escape_base = None;
+                } else if allow_symlink_escape
+                    && (escape_base.is_some() || resolved.starts_with(root))
+                {
+                    escape_base = Some(target.clone());
+                }
+                resolved = target;
+            }
+        }
+    }
+    if resolved.starts_with(root) || escape_base.is_some() {
+        Ok(Some(resolved))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn path_to_uri(path: &Path) -> Cow<'_, str> {
+    match Url::from_file_path(path) {
+        Ok(uri) => Cow::Owned(uri.into()),
+        Err(()) => path.to_string_lossy(),
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().as_encoded_bytes().starts_with(b".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_lexically_resolves_dots() {
+        assert_eq!(
+            normalize_lexically(Path::new("/a/./b/../c/d.pdf")),
+            Path::new("/a/c/d.pdf")
+        );
+        assert_eq!(
+            normalize_lexically(Path::new("/a/../../b")),
+            Path::new("/b")
+        );
+        assert_eq!(normalize_lexically(Path::new("a/b/..")), Path::new("a"));
+    }
+
+    /// Creates `root/a.pdf`, `outside/secret.pdf` and `outside/sub/doc.pdf`, with the symbolic
+    /// links `root/link` to `outside`, and `root/inner` to `root/dir`.
+    #[cfg(unix)]
+    fn create_test_tree(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let base =
+            std::env::temp_dir().join(format!("pdflens-mcp-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        std::fs::create_dir_all(base.join("root/dir")).unwrap();
+        std::fs::create_dir_all(base.join("outside/sub")).unwrap();
+        let base = std::fs::canonicalize(base).unwrap();
+        let (root, outside) = (base.join("root"), base.join("outside"));
+        for file in [
+            root.join("a.pdf"),
+            root.join("dir/b.pdf"),
+            outside.join("secret.pdf"),
+            outside.join("sub/doc.pdf"),
+        ] {
+            std::fs::write(file, b"").unwrap();
+        }
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root.join("dir"), root.join("inner")).unwrap();
+        (base, root, outside)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_in_root_rejects_escapes() {
+        let (base, root, outside) = create_test_tree("resolve-strict");
+        let resolve = |path: &str| resolve_in_root(&root.join(path), &root, false).unwrap();
+
+        assert_eq!(resolve("a.pdf"), Some(root.join("a.pdf")));
+        assert_eq!(resolve("./dir/../a.pdf"), Some(root.join("a.pdf")));
+        assert_eq!(resolve("inner/b.pdf"), Some(root.join("dir/b.pdf")));
+        assert_eq!(resolve("../outside/secret.pdf"), None);
+        assert_eq!(resolve("link/secret.pdf"), None);
+        // The link is followed before `..`, like the OS does
+        assert_eq!(resolve("link/../outside/secret.pdf"), None);
+        assert_eq!(resolve("link/../root/a.pdf"), Some(root.join("a.pdf")));
+        assert_eq!(resolve("dir/../../root/a.pdf"), Some(root.join("a.pdf")));
+        assert!(resolve_in_root(&root.join("missing.pdf"), &root, false).is_err());
+        assert!(
+            resolve_in_root(&outside.join("secret.pdf"), &root, false)
+                .unwrap()
+                .is_none()
+        );
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_in_root_allows_symlink_escape() {
+        let (base, root, outside) = create_test_tree("resolve-escape");
+        let resolve = |path: &str| resolve_in_root(&root.join(path), &root, true).unwrap();
+
+        assert_eq!(resolve("link/secret.pdf"), Some(outside.join("secret.pdf")));
+        assert_eq!(
+            resolve("link/sub/../secret.pdf"),
+            Some(outside.join("secret.pdf"))
+        );
+        // Climbing out of the link target is only allowed back into the root
+        assert_eq!(resolve("link/../outside/secret.pdf"), None);
+        assert_eq!(resolve("link/../root/a.pdf"), Some(root.join("a.pdf")));
+        assert_eq!(resolve("../outside/secret.pdf"), None);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}